@@ -2,170 +2,301 @@ use crate::parser::*;
 use crate::opcodes::*;
 use std::collections::HashMap;
 
-pub fn emit(insts: &[Inst]) -> Result<Vec<u16>, String> {
+// Instruction fields before packing. `imm` is set for instructions that take
+// an immediate or label operand; it lives in the low nibble when it fits and
+// in trailing words behind a prefix otherwise. `zext` marks immediates the
+// CPU zero-extends, which changes what "fits" means.
+struct Fields {
+    group: u8,
+    op: u8,
+    rd: u8,
+    rs1: u8,
+    rs2: u8,
+    imm: Option<i64>,
+    zext: bool,
+}
+
+// Immediate size code as carried by the prefix word:
+// 0 = imm4, 1 = 16-bit, 2 = 32-bit, 3 = 64-bit.
+pub fn imm_size(v: i64) -> u8 {
+    if (-8..=7).contains(&v) {
+        0
+    } else if i16::try_from(v).is_ok() {
+        1
+    } else if i32::try_from(v).is_ok() {
+        2
+    } else {
+        3
+    }
+}
+
+pub fn uimm_size(v: i64) -> u8 {
+    match v as u64 {
+        0..=0xF => 0,
+        0x10..=0xFFFF => 1,
+        0x1_0000..=0xFFFF_FFFF => 2,
+        _ => 3,
+    }
+}
+
+fn imm_words(size: u8) -> u64 {
+    [0, 1, 2, 4][size as usize]
+}
+
+pub fn len_bytes(group: u8, size: u8) -> u64 {
+    if group == GROUP_BASE && size == 0 {
+        2
+    } else {
+        4 + imm_words(size) * 2
+    }
+}
+
+/// Encodes `insts` into instruction words. Problems that still assemble, such
+/// as writing r0, are returned alongside the code as warnings for the caller
+/// to report.
+pub fn emit(insts: &[Inst]) -> Result<(Vec<u16>, Vec<String>), String> {
+    let mut groups = Vec::with_capacity(insts.len());
+    for inst in insts {
+        groups.push(match inst {
+            Inst::Op(name, args) => select(name, args)?.0,
+            Inst::Label(_) => GROUP_BASE,
+        });
+    }
+
+    // Widening an immediate moves every later label, which can push other
+    // offsets out of range, so keep laying the program out until nothing
+    // grows. Sizes only ever increase, so this terminates.
+    let mut sizes = vec![0u8; insts.len()];
+    loop {
+        let labels = layout(insts, &groups, &sizes);
+        let mut grew = false;
+        let mut pc = 0u64;
+
+        for (k, inst) in insts.iter().enumerate() {
+            if let Inst::Op(name, args) = inst {
+                let next_pc = pc + len_bytes(groups[k], sizes[k]);
+                let f = fields(name, args, next_pc, &labels)?;
+                let need = match f.imm {
+                    Some(v) if f.zext => uimm_size(v),
+                    Some(v) => imm_size(v),
+                    None => 0,
+                };
+                if need > sizes[k] {
+                    sizes[k] = need;
+                    grew = true;
+                }
+                pc = next_pc;
+            }
+        }
+
+        if !grew {
+            break;
+        }
+    }
+
+    let labels = layout(insts, &groups, &sizes);
     let mut out = Vec::new();
-    let mut labels = HashMap::new();
+    let mut warnings = Vec::new();
+    let mut pc = 0u64;
 
+    for (k, inst) in insts.iter().enumerate() {
+        if let Inst::Op(name, args) = inst {
+            // r0 is hardwired to zero, so the result would be thrown away.
+            match args.first() {
+                Some(Arg::Reg(r)) if writes_rd(name) && reg_index(r) == Some(0) => {
+                    warnings.push(format!("{} at {:#x} writes r0, which always reads as zero", name, pc));
+                }
+                _ => {}
+            }
+            let next_pc = pc + len_bytes(groups[k], sizes[k]);
+            let f = fields(name, args, next_pc, &labels)?;
+            pack(&f, sizes[k], &mut out);
+            pc = next_pc;
+        }
+    }
+
+    Ok((out, warnings))
+}
+
+fn layout(insts: &[Inst], groups: &[u8], sizes: &[u8]) -> HashMap<String, u64> {
+    let mut labels = HashMap::new();
     let mut pc = 0u64;
-    for inst in insts {
+    for (k, inst) in insts.iter().enumerate() {
         match inst {
             Inst::Label(s) => {
                 labels.insert(s.clone(), pc);
             }
-            Inst::Op(_, _) => pc += 2,
+            Inst::Op(_, _) => pc += len_bytes(groups[k], sizes[k]),
         }
     }
+    labels
+}
 
-    let mut current_pc = 0u64;
-    for inst in insts {
-        if let Inst::Op(name, args) = inst {
-            let op = opcode(name)
-                .ok_or_else(|| format!("unknown instruction '{}'", name))?;
-
-            let mut rd = 0u8;
-            let mut rs1 = 0u8;
-            let mut imm4 = 0u8;
-
-            // Parse based on instruction type
-            match name.as_str() {
-                "syscall" => {
-                    // syscall rs1 - syscall number in rs1
-                    if args.is_empty() {
-                        return Err("syscall requires register argument".to_string());
-                    }
-                    match &args[0] {
-                        Arg::Reg(r) => rs1 = reg_index(r)
-                            .ok_or_else(|| format!("invalid register '{}'", r))?,
-                        _ => return Err("syscall requires register".to_string()),
-                    }
-                }
-                "ret" => {
-                    // ret has no arguments
-                }
-                "brz" => {
-                    // brz rs1, label/imm
-                    if args.len() < 2 {
-                        return Err("brz requires 2 arguments".to_string());
-                    }
-                    match &args[0] {
-                        Arg::Reg(r) => rs1 = reg_index(r)
-                            .ok_or_else(|| format!("invalid register '{}'", r))?,
-                        _ => return Err("brz arg1 must be register".to_string()),
-                    }
-                    match &args[1] {
-                        Arg::Imm(v) => imm4 = *v as i8 as u8,
-                        Arg::Label(s) => {
-                            let target = *labels.get(s)
-                                .ok_or_else(|| format!("undefined label '{}'", s))?;
-                            let offset = ((target as i64 - (current_pc as i64 + 2)) / 2) as i8;
-                            imm4 = offset as u8;
-                        }
-                        _ => return Err("brz arg2 must be immediate or label".to_string()),
-                    }
-                }
-                "br" => {
-                    // br rs1, rs2, label/imm
-                    if args.len() < 3 {
-                        return Err("br requires 3 arguments".to_string());
-                    }
-                    match &args[0] {
-                        Arg::Reg(r) => rs1 = reg_index(r)
-                            .ok_or_else(|| format!("invalid register '{}'", r))?,
-                        _ => return Err("br arg1 must be register".to_string()),
-                    }
-                    match &args[1] {
-                        Arg::Reg(r) => rd = reg_index(r)
-                            .ok_or_else(|| format!("invalid register '{}'", r))?,
-                        _ => return Err("br arg2 must be register".to_string()),
-                    }
-                    match &args[2] {
-                        Arg::Imm(v) => imm4 = *v as i8 as u8,
-                        Arg::Label(s) => {
-                            let target = *labels.get(s)
-                                .ok_or_else(|| format!("undefined label '{}'", s))?;
-                            let offset = ((target as i64 - (current_pc as i64 + 2)) / 2) as i8;
-                            imm4 = offset as u8;
-                        }
-                        _ => return Err("br arg3 must be immediate or label".to_string()),
-                    }
-                }
-            "jmp" | "call" => {
-                // first argument = register (r0 = PC-relative)
-                rs1 = match &args[0] {
-                    Arg::Reg(r) => reg_index(r)
-                        .ok_or_else(|| format!("invalid register '{}'", r))?,
-                    _ => return Err(format!("{} needs register as first argument", name)),
-                };
+fn pack(f: &Fields, size: u8, out: &mut Vec<u16>) {
+    let low = match f.imm {
+        Some(v) if size == 0 => (v as u16) & 0xF,  // Mask to 4 bits to avoid overflow into rs1
+        Some(_) => 0,
+        None => f.rs2 as u16,
+    };
 
-                // second argument (optional) = imm or label
-                let offset_i8 = if args.len() > 1 {
-                    match &args[1] {
-                        Arg::Imm(n) => {
-                            let v = *n as i8;
-                            if v < -8 || v > 7 {
-                                return Err(format!("offset {} does not fit in 4-bit signed immediate", v));
-                            }
-                            v
-                        }
-                        Arg::Label(lab) => {
-                            let target = *labels.get(lab)
-                                .ok_or_else(|| format!("undefined label '{}'", lab))?;
-                            let diff = (target as i64 - (current_pc as i64 + 2)) / 2;
-                            if diff < -8 || diff > 7 {
-                                return Err(format!("jump to {} is too far (±7 instructions max)", lab));
-                            }
-                            diff as i8
-                        }
-                        _ => return Err("second argument must be immediate or label".to_string()),
-                    }
-                } else {
-                    0i8
-                };
+    if f.group != GROUP_BASE || size != 0 {
+        out.push(((OP_EXT as u16) << 12) | ((f.group as u16) << 8) | ((size as u16) << 6));
+    }
+
+    out.push(((f.op as u16) << 12)
+        | ((f.rd as u16) << 8)
+        | ((f.rs1 as u16) << 4)
+        | low);
+
+    let v = f.imm.unwrap_or(0) as u64;
+    for k in 0..imm_words(size) {
+        out.push((v >> (16 * k)) as u16);
+    }
+}
+
+fn reg(arg: &Arg, what: &str) -> Result<u8, String> {
+    match arg {
+        Arg::Reg(r) => reg_index(r).ok_or_else(|| format!("invalid register '{}'", r)),
+        _ => Err(format!("{} must be register", what)),
+    }
+}
+
+// Branch and jump targets are encoded in instruction words (2 bytes) relative
+// to the next instruction.
+fn target(arg: &Arg, next_pc: u64, labels: &HashMap<String, u64>, what: &str) -> Result<i64, String> {
+    match arg {
+        Arg::Imm(v) => Ok(*v),
+        Arg::Label(s) => {
+            let target = *labels.get(s)
+                .ok_or_else(|| format!("undefined label '{}'", s))?;
+            Ok((target as i64 - next_pc as i64) / 2)
+        }
+        _ => Err(format!("{} must be immediate or label", what)),
+    }
+}
 
-                imm4 = offset_i8 as u8;
+// Picks (group, opcode). Loads and stores whose address operand is based on a
+// capability, as in `ld r1, 8(c3)`, use the capability-relative group, and
+// `jmp`/`call` through a capability register switch to their PCC-installing
+// forms.
+fn select(name: &str, args: &[Arg]) -> Result<(u8, u8), String> {
+    if let (Some(Arg::Cap(_)), Some(op)) = (args.first(), cap_jump(name)) {
+        return Ok((GROUP_CAP, op));
+    }
+    match args.get(1) {
+        Some(Arg::Mem(_, base)) if base.starts_with('c') => {
+            let op = cap_relative(name)
+                .ok_or_else(|| format!("{} cannot address through a capability", name))?;
+            Ok((GROUP_CMEM, op))
+        }
+        _ => opcode(name).ok_or_else(|| format!("unknown instruction '{}'", name)),
+    }
+}
+
+fn fields(name: &str, args: &[Arg], next_pc: u64, labels: &HashMap<String, u64>) -> Result<Fields, String> {
+    let (group, op) = select(name, args)?;
+
+    let mut f = Fields { group, op, rd: 0, rs1: 0, rs2: 0, imm: None, zext: false };
+
+    // Parse based on instruction type
+    match name {
+        "syscall" => {
+            // syscall rs1 - syscall number in rs1
+            if args.is_empty() {
+                return Err("syscall requires register argument".to_string());
             }
-                _ => {
-                    // Standard 3-operand format: op rd, rs1, rs2/imm
-                    if !args.is_empty() {
-                        match &args[0] {
-                            Arg::Reg(r) => rd = reg_index(r)
-                                .ok_or_else(|| format!("invalid register '{}'", r))?,
-                            Arg::Cap(c) => rd = cap_index(c)
-                                .ok_or_else(|| format!("invalid capability '{}'", c))?,
-                            _ => {}
-                        }
-                    }
-                    if args.len() > 1 {
-                        match &args[1] {
-                            Arg::Reg(r) => rs1 = reg_index(r)
-                                .ok_or_else(|| format!("invalid register '{}'", r))?,
-                            Arg::Cap(c) => rs1 = cap_index(c)
-                                .ok_or_else(|| format!("invalid capability '{}'", c))?,
-                            _ => {}
-                        }
-                    }
-                    if args.len() > 2 {
-                        match &args[2] {
-                            Arg::Reg(r) => imm4 = reg_index(r)
-                                .ok_or_else(|| format!("invalid register '{}'", r))?,
-                            Arg::Cap(c) => imm4 = cap_index(c)
-                                .ok_or_else(|| format!("invalid capability '{}'", c))?,
-                            Arg::Imm(v) => imm4 = *v as i8 as u8,
-                            _ => {}
-                        }
+            f.rs1 = reg(&args[0], "syscall arg")?;
+        }
+        "ret" | "pret" | "eret" => {
+            // ret has no arguments
+        }
+        "brz" => {
+            // brz rs1, label/imm
+            if args.len() < 2 {
+                return Err("brz requires 2 arguments".to_string());
+            }
+            f.rs1 = reg(&args[0], "brz arg1")?;
+            f.imm = Some(target(&args[1], next_pc, labels, "brz arg2")?);
+        }
+        "br" | "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" => {
+            // br rs1, rs2, label/imm (rs2 travels in the rd field)
+            if args.len() < 3 {
+                return Err(format!("{} requires 3 arguments", name));
+            }
+            f.rs1 = reg(&args[0], &format!("{} arg1", name))?;
+            f.rd = reg(&args[1], &format!("{} arg2", name))?;
+            f.imm = Some(target(&args[2], next_pc, labels, &format!("{} arg3", name))?);
+        }
+        "lsi" | "lzi" | "lui" | "trap.get" => {
+            // lsi rd, imm
+            if args.len() < 2 {
+                return Err(format!("{} requires 2 arguments", name));
+            }
+            f.rd = reg(&args[0], &format!("{} arg1", name))?;
+            f.imm = match &args[1] {
+                Arg::Imm(v) => Some(*v),
+                _ => return Err(format!("{} arg2 must be immediate", name)),
+            };
+            f.zext = name == "lzi";
+        }
+        "jmp" | "call" | "pcall" => {
+            // first argument = register (r0 = PC-relative) or code capability
+            if args.is_empty() {
+                return Err(format!("{} needs register as first argument", name));
+            }
+            f.rs1 = match &args[0] {
+                Arg::Cap(c) if group == GROUP_CAP => cap_index(c)
+                    .ok_or_else(|| format!("invalid capability '{}'", c))?,
+                a => reg(a, &format!("{} arg1", name))?,
+            };
+
+            // second argument (optional) = imm or label
+            if args.len() > 1 {
+                f.imm = Some(target(&args[1], next_pc, labels, "second argument")?);
+            }
+        }
+        _ => {
+            // Standard 3-operand format: op rd, rs1, rs2/imm
+            if !args.is_empty() {
+                match &args[0] {
+                    Arg::Reg(r) => f.rd = reg_index(r)
+                        .ok_or_else(|| format!("invalid register '{}'", r))?,
+                    Arg::Cap(c) => f.rd = cap_index(c)
+                        .ok_or_else(|| format!("invalid capability '{}'", c))?,
+                    _ => {}
+                }
+            }
+            if args.len() > 1 {
+                match &args[1] {
+                    Arg::Reg(r) => f.rs1 = reg_index(r)
+                        .ok_or_else(|| format!("invalid register '{}'", r))?,
+                    Arg::Cap(c) => f.rs1 = cap_index(c)
+                        .ok_or_else(|| format!("invalid capability '{}'", c))?,
+                    Arg::Mem(off, base) => {
+                        f.rs1 = if group == GROUP_CMEM {
+                            cap_index(base)
+                                .ok_or_else(|| format!("invalid capability '{}'", base))?
+                        } else {
+                            reg_index(base)
+                                .ok_or_else(|| format!("invalid register '{}'", base))?
+                        };
+                        f.imm = Some(*off);
                     }
+                    _ => {}
+                }
+            }
+            if args.len() > 2 {
+                match &args[2] {
+                    Arg::Reg(r) => f.rs2 = reg_index(r)
+                        .ok_or_else(|| format!("invalid register '{}'", r))?,
+                    Arg::Cap(c) => f.rs2 = cap_index(c)
+                        .ok_or_else(|| format!("invalid capability '{}'", c))?,
+                    Arg::Imm(v) => f.imm = Some(*v),
+                    _ => {}
                 }
             }
-
-            let raw = ((op as u16) << 12)
-                | ((rd as u16) << 8)
-                | ((rs1 as u16) << 4)
-                | ((imm4 as u16) & 0xF);  // Mask to 4 bits to avoid overflow into rs1
-
-            out.push(raw);
-            current_pc += 2;
         }
     }
 
-    Ok(out)
-}
\ No newline at end of file
+    Ok(f)
+}
@@ -4,6 +4,8 @@ pub enum Tok {
     Number(i64),
     Colon,
     Comma,
+    LParen,
+    RParen,
     Newline,
 }
 
@@ -43,6 +45,13 @@ pub fn lex(src: &str) -> Result<Vec<Tok>, String> {
                 }
                 toks.push(Tok::Comma);
             }
+            '(' | ')' => {
+                if !cur.is_empty() {
+                    toks.push(parse_token(&cur));
+                    cur.clear();
+                }
+                toks.push(if ch == '(' { Tok::LParen } else { Tok::RParen });
+            }
             '\n' => {
                 if !cur.is_empty() {
                     toks.push(parse_token(&cur));
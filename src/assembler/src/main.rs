@@ -2,6 +2,7 @@ mod lexer;
 mod parser;
 mod opcodes;
 mod emitter;
+mod pseudo;
 mod bin;
 
 use std::env;
@@ -13,11 +14,15 @@ fn assemble_file(path: &str, out: &str) -> Result<(), String> {
 
     let tokens = lexer::lex(&input)?;
     let ast = parser::parse(&tokens)?;
-    let encoded = emitter::emit(&ast)?;
+    let ast = pseudo::expand(&ast)?;
+    let (encoded, warnings) = emitter::emit(&ast)?;
+    for w in &warnings {
+        eprintln!("warning: {}", w);
+    }
 
     bin::write_osl_bin(out, &encoded, 0x1000, 0x200000)?;
 
-    println!("Assembled {} instructions", encoded.len());
+    println!("Assembled {} instruction words", encoded.len());
     Ok(())
 }
 
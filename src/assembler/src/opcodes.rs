@@ -1,39 +1,140 @@
-pub fn opcode(name: &str) -> Option<u8> {
+pub const OP_EXT: u8 = 0xB;
+
+pub const GROUP_BASE: u8 = 0x0;
+pub const GROUP_IMM: u8 = 0x1;
+pub const GROUP_ALU: u8 = 0x2;
+pub const GROUP_BRANCH: u8 = 0x3;
+pub const GROUP_MEM: u8 = 0x4;
+pub const GROUP_CMEM: u8 = 0x5;
+pub const GROUP_CAP: u8 = 0x6;
+pub const GROUP_STACK: u8 = 0x7;
+pub const GROUP_TRAP: u8 = 0x8;
+pub const GROUP_COUNTER: u8 = 0x9;
+
+/// Returns the (extension group, opcode) pair for a mnemonic. Group 0 is the
+/// single-word base opcode space; other groups are reached through the 0xB
+/// prefix word.
+pub fn opcode(name: &str) -> Option<(u8, u8)> {
     Some(match name {
-        "add"     => 0x0,
-        "addi"    => 0x1,
-        "div"     => 0x2,
-        "sub"     => 0x3,
-        "mul"     => 0x4,
-        "ld"      => 0x5,
-        "st"      => 0x6,
-        "br"      => 0x7,
-        "brz"     => 0x8,
-        "jmp"     => 0x9,
-        "call"    => 0xA,
-        "ret"     => 0xB,
-        "syscall" => 0xC,
-        "cap.null"   => 0xD,
-        "cap.copy"   => 0xE,
-        "cap.offset" => 0xF,
+        "add"     => (GROUP_BASE, 0x0),
+        "addi"    => (GROUP_BASE, 0x1),
+        "div" | "divu" => (GROUP_BASE, 0x2),
+        "sub"     => (GROUP_BASE, 0x3),
+        "mul"     => (GROUP_BASE, 0x4),
+        "ld"      => (GROUP_BASE, 0x5),
+        "st"      => (GROUP_BASE, 0x6),
+        "br" | "beq" => (GROUP_BASE, 0x7),
+        "brz"     => (GROUP_BASE, 0x8),
+        "jmp"     => (GROUP_BASE, 0x9),
+        "call"    => (GROUP_BASE, 0xA),
+        "ret"     => (GROUP_BASE, 0xB),
+        "syscall" => (GROUP_BASE, 0xC),
+        "cap.null"   => (GROUP_BASE, 0xD),
+        "cap.copy"   => (GROUP_BASE, 0xE),
+        "cap.offset" => (GROUP_BASE, 0xF),
+        "lsi"     => (GROUP_IMM, 0x0),
+        "lzi"     => (GROUP_IMM, 0x1),
+        "lui"     => (GROUP_IMM, 0x2),
+        "and"     => (GROUP_ALU, 0x0),
+        "or"      => (GROUP_ALU, 0x1),
+        "xor"     => (GROUP_ALU, 0x2),
+        "not"     => (GROUP_ALU, 0x3),
+        "shl"     => (GROUP_ALU, 0x4),
+        "shr"     => (GROUP_ALU, 0x5),
+        "sar"     => (GROUP_ALU, 0x6),
+        "divs"    => (GROUP_ALU, 0x7),
+        "remu"    => (GROUP_ALU, 0x8),
+        "rems"    => (GROUP_ALU, 0x9),
+        "slt"     => (GROUP_ALU, 0xA),
+        "sltu"    => (GROUP_ALU, 0xB),
+        "bne"     => (GROUP_BRANCH, 0x0),
+        "blt"     => (GROUP_BRANCH, 0x1),
+        "bge"     => (GROUP_BRANCH, 0x2),
+        "bltu"    => (GROUP_BRANCH, 0x3),
+        "bgeu"    => (GROUP_BRANCH, 0x4),
+        "ld8"     => (GROUP_MEM, 0x0),
+        "ld8u"    => (GROUP_MEM, 0x1),
+        "ld16"    => (GROUP_MEM, 0x2),
+        "ld16u"   => (GROUP_MEM, 0x3),
+        "ld32"    => (GROUP_MEM, 0x4),
+        "ld32u"   => (GROUP_MEM, 0x5),
+        "st8"     => (GROUP_MEM, 0x7),
+        "st16"    => (GROUP_MEM, 0x8),
+        "st32"    => (GROUP_MEM, 0x9),
+        "cap.load"  => (GROUP_MEM, 0xB),
+        "cap.store" => (GROUP_MEM, 0xC),
+        "push"    => (GROUP_STACK, 0x0),
+        "pop"     => (GROUP_STACK, 0x1),
+        "pcall"   => (GROUP_STACK, 0x2),
+        "pret"    => (GROUP_STACK, 0x3),
+        "cap.setbounds" => (GROUP_CAP, 0x0),
+        "cap.andperm"   => (GROUP_CAP, 0x1),
+        "cap.seal"      => (GROUP_CAP, 0x2),
+        "cap.unseal"    => (GROUP_CAP, 0x3),
+        "cap.invoke"    => (GROUP_CAP, 0x4),
+        "cap.return"    => (GROUP_CAP, 0x5),
+        "cap.getbase"   => (GROUP_CAP, 0x6),
+        "cap.getlen"    => (GROUP_CAP, 0x7),
+        "cap.getoff"    => (GROUP_CAP, 0x8),
+        "cap.getperm"   => (GROUP_CAP, 0x9),
+        "cap.gettag"    => (GROUP_CAP, 0xA),
+        "cap.getaddr"   => (GROUP_CAP, 0xB),
+        "cap.getsealed" => (GROUP_CAP, 0xC),
+        "cap.gettype"   => (GROUP_CAP, 0xD),
+        "eret"        => (GROUP_TRAP, 0x0),
+        "trap.get"    => (GROUP_TRAP, 0x1),
+        "trap.setepc" => (GROUP_TRAP, 0x2),
+        "cap.settvec" => (GROUP_TRAP, 0x3),
+        "cap.gettvec" => (GROUP_TRAP, 0x4),
+        "cap.getroot" => (GROUP_TRAP, 0x5),
+        "timer.set"   => (GROUP_TRAP, 0x6),
+        "rdcycle"     => (GROUP_COUNTER, 0x0),
+        "rdinstret"   => (GROUP_COUNTER, 0x1),
         _ => return None,
     })
 }
 
-pub fn reg_index(s: &str) -> Option<u8> {
-    if s.starts_with('r') {
-        let idx: u8 = s[1..].parse().ok()?;
-        if idx < 16 { Some(idx) } else { None }
-    } else {
-        None
+/// Opcode of `jmp cN` / `call cN`, which install a new program counter
+/// capability.
+pub fn cap_jump(name: &str) -> Option<u8> {
+    match name {
+        "jmp" => Some(0xE),
+        "call" => Some(0xF),
+        _ => None,
     }
 }
 
-pub fn cap_index(s: &str) -> Option<u8> {
-    if s.starts_with('c') {
-        let idx: u8 = s[1..].parse().ok()?;
-        if idx < 8 { Some(idx) } else { None }
-    } else {
-        None
+/// Whether the first operand of `name` is a destination register. Stores and
+/// `push` read it instead, and two-register branches compare against it.
+pub fn writes_rd(name: &str) -> bool {
+    match opcode(name) {
+        Some((GROUP_BASE, op)) => op <= 0x5,
+        Some((GROUP_IMM | GROUP_ALU, _)) => true,
+        Some((GROUP_MEM, op)) => op <= 0x6,
+        Some((GROUP_STACK, op)) => op == 0x1,
+        Some((GROUP_CAP, op)) => (0x6..=0xD).contains(&op),
+        Some((GROUP_TRAP, op)) => op == 0x1,
+        Some((GROUP_COUNTER, _)) => true,
+        _ => false,
+    }
+}
+
+/// Opcode of the capability-relative (group CMEM) form of a load or store.
+pub fn cap_relative(name: &str) -> Option<u8> {
+    match (name, opcode(name)?) {
+        ("ld", _) => Some(0x6),
+        ("st", _) => Some(0xA),
+        (_, (GROUP_MEM, op)) => Some(op),
+        _ => None,
     }
 }
+
+pub fn reg_index(s: &str) -> Option<u8> {
+    let idx: u8 = s.strip_prefix('r')?.parse().ok()?;
+    if idx < 16 { Some(idx) } else { None }
+}
+
+pub fn cap_index(s: &str) -> Option<u8> {
+    let idx: u8 = s.strip_prefix('c')?.parse().ok()?;
+    if idx < 8 { Some(idx) } else { None }
+}
@@ -12,6 +12,8 @@ pub enum Arg {
     Cap(String),
     Imm(i64),
     Label(String),
+    /// `imm(base)` where base is a register or capability name.
+    Mem(i64, String),
 }
 
 pub fn parse(toks: &[Tok]) -> Result<Vec<Inst>, String> {
@@ -42,8 +44,16 @@ pub fn parse(toks: &[Tok]) -> Result<Vec<Inst>, String> {
                                 i += 1;
                             }
                             Tok::Number(n) => {
-                                args.push(Arg::Imm(*n));
-                                i += 1;
+                                if i + 1 < toks.len() && matches!(toks[i + 1], Tok::LParen) {
+                                    i += 1;
+                                    args.push(parse_mem(toks, &mut i, *n)?);
+                                } else {
+                                    args.push(Arg::Imm(*n));
+                                    i += 1;
+                                }
+                            }
+                            Tok::LParen => {
+                                args.push(parse_mem(toks, &mut i, 0)?);
                             }
                             Tok::Comma => {
                                 i += 1;
@@ -68,3 +78,14 @@ pub fn parse(toks: &[Tok]) -> Result<Vec<Inst>, String> {
 
     Ok(out)
 }
+
+// Parses `(base)` starting at the opening parenthesis.
+fn parse_mem(toks: &[Tok], i: &mut usize, off: i64) -> Result<Arg, String> {
+    match (toks.get(*i), toks.get(*i + 1), toks.get(*i + 2)) {
+        (Some(Tok::LParen), Some(Tok::Ident(base)), Some(Tok::RParen)) => {
+            *i += 3;
+            Ok(Arg::Mem(off, base.clone()))
+        }
+        _ => Err(format!("malformed memory operand at position {}", *i)),
+    }
+}
@@ -0,0 +1,66 @@
+use crate::parser::*;
+use crate::opcodes::*;
+use crate::emitter::{imm_size, uimm_size, len_bytes};
+
+/// Rewrites pseudo-instructions into real ones.
+pub fn expand(insts: &[Inst]) -> Result<Vec<Inst>, String> {
+    let mut out = Vec::with_capacity(insts.len());
+
+    for inst in insts {
+        match inst {
+            Inst::Op(name, args) if name == "li" => {
+                // li rd, imm
+                if args.len() < 2 {
+                    return Err("li requires 2 arguments".to_string());
+                }
+                let rd = match &args[0] {
+                    Arg::Reg(r) => r,
+                    _ => return Err("li arg1 must be register".to_string()),
+                };
+                let v = match &args[1] {
+                    Arg::Imm(v) => *v,
+                    _ => return Err("li arg2 must be immediate".to_string()),
+                };
+                out.extend(li(rd, v));
+            }
+            _ => out.push(inst.clone()),
+        }
+    }
+
+    Ok(out)
+}
+
+/// Shortest instruction sequence that loads `v` into register `rd`.
+pub fn li(rd: &str, v: i64) -> Vec<Inst> {
+    let op = |name: &str, args: Vec<Arg>| Inst::Op(name.to_string(), args);
+    let reg = |r: &str| Arg::Reg(r.to_string());
+
+    if imm_size(v) == 0 {
+        // r0 = zero
+        return vec![op("addi", vec![reg(rd), reg("r0"), Arg::Imm(v)])];
+    }
+
+    let mut candidates = vec![
+        (len_bytes(GROUP_IMM, imm_size(v)), vec![op("lsi", vec![reg(rd), Arg::Imm(v)])]),
+        (len_bytes(GROUP_IMM, uimm_size(v)), vec![op("lzi", vec![reg(rd), Arg::Imm(v)])]),
+    ];
+
+    // lui sets the upper half; a sign-extended addi fixes up the lower one.
+    let lo = v as i32 as i64;
+    let hi = ((v.wrapping_sub(lo) as u64) >> 32) as u32 as i32 as i64;
+    let mut seq = vec![op("lui", vec![reg(rd), Arg::Imm(hi)])];
+    let mut len = len_bytes(GROUP_IMM, imm_size(hi));
+    if lo != 0 {
+        seq.push(op("addi", vec![reg(rd), reg(rd), Arg::Imm(lo)]));
+        len += len_bytes(GROUP_BASE, imm_size(lo));
+    }
+    candidates.push((len, seq));
+
+    let mut best = candidates.remove(0);
+    for c in candidates {
+        if c.0 < best.0 {
+            best = c;
+        }
+    }
+    best.1
+}
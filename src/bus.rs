@@ -0,0 +1,20 @@
+use crate::mem::Memory;
+use crate::trap::Trap;
+
+/// A memory-mapped peripheral. Offsets are relative to the start of its
+/// mapping, `size` is 1, 2, 4 or 8 bytes and reads are zero-extended.
+pub trait Device {
+    fn read(&mut self, offset: u64, size: u64) -> u64;
+    fn write(&mut self, offset: u64, size: u64, val: u64);
+
+    /// Runs after each write to the device, for devices that move data to or
+    /// from RAM themselves.
+    fn dma(&mut self, _mem: &mut Memory) {}
+}
+
+/// Physical address space: routes an access to RAM or to the device mapped at
+/// that address. Callers check capabilities before going through it.
+pub trait Bus {
+    fn read(&mut self, addr: u64, size: u64) -> Result<u64, Trap>;
+    fn write(&mut self, addr: u64, size: u64, val: u64) -> Result<(), Trap>;
+}
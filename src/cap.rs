@@ -1,4 +1,10 @@
-#[derive(Clone, Copy, Debug)]
+/// Bytes a capability occupies in memory; also the tag granule size.
+pub const CAP_SIZE: u64 = 16;
+
+/// Number of object types handed out by the loader's sealing root.
+pub const OTYPE_COUNT: u64 = 1 << 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Capability {
     pub base: u64,
     pub length: u64,
@@ -6,6 +12,10 @@ pub struct Capability {
     pub perms: u8,
     pub valid: bool,
     pub sealed: bool,
+    /// Object type a sealed capability was sealed with. Every value, 0
+    /// included, is a usable type: `sealed` says whether the capability is
+    /// sealed, and unsealed capabilities just carry 0.
+    pub otype: u64,
 }
 
 impl Capability {
@@ -17,16 +27,22 @@ impl Capability {
             perms: 0,
             valid: false,
             sealed: false,
+            otype: 0,
         }
     }
 
     pub fn can_read(&self) -> bool { self.perms & 1 != 0 }
     pub fn can_write(&self) -> bool { self.perms & 2 != 0 }
     pub fn can_exec(&self) -> bool { self.perms & 4 != 0 }
+    pub fn can_load_cap(&self) -> bool { self.perms & 8 != 0 }
+    pub fn can_store_cap(&self) -> bool { self.perms & 0x10 != 0 }
+    /// Code running under a PCC with this permission is in supervisor mode.
+    pub fn can_access_system(&self) -> bool { self.perms & 0x20 != 0 }
+    pub fn can_unseal(&self) -> bool { self.perms & 0x40 != 0 }
     pub fn can_seal(&self) -> bool { self.perms & 0x80 != 0 }
 
     pub fn in_bounds(&self, off: u64, size: u64) -> bool {
-        off.checked_add(size).map_or(false, |end| end <= self.length)
+        off.checked_add(size).is_some_and(|end| end <= self.length)
     }
 
     pub fn get_address(&self) -> u64 {
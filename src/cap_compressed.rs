@@ -0,0 +1,151 @@
+// 128-bit in-memory capability format, modelled on CHERI Concentrate.
+//
+// The first word is the address (base + offset). The second packs the
+// metadata:
+//
+//     [7:0] perms  [8] sealed  [24:9] otype  [30:25] E  [46:31] B  [62:47] T
+//
+// B and T are bits [E + MW - 1 : E] of the base and top. The remaining upper
+// bits are recovered from the address, which works as long as the address
+// stays inside the representable region: 2^(E + MW) bytes starting a quarter
+// of that below the base.
+
+use crate::cap::Capability;
+
+/// Mantissa width of B and T.
+pub const MW: u32 = 16;
+
+const MASK: u64 = (1 << MW) - 1;
+const OTYPE_BITS: u32 = 16;
+
+// Bounds must span fewer than three quarters of the region so that the top
+// decodes unambiguously.
+const MAX_SPAN: u128 = 3 << (MW - 2);
+
+/// Smallest exponent with which [base, top) can be encoded after rounding
+/// base down and top up to multiples of 2^E.
+fn exponent(base: u64, top: u128) -> u32 {
+    let mut e = 0;
+    loop {
+        let lo = (base as u128) >> e;
+        let hi = (top + (1 << e) - 1) >> e;
+        if hi - lo < MAX_SPAN {
+            return e;
+        }
+        e += 1;
+    }
+}
+
+/// The smallest encodable bounds containing [base, base + length), as
+/// (base, length). Lengths of 2^64 saturate to u64::MAX.
+pub fn round_bounds(base: u64, length: u64) -> (u64, u64) {
+    let top = base as u128 + length as u128;
+    let e = exponent(base, top);
+    let b = ((base as u128) >> e) << e;
+    let t = ((top + (1 << e) - 1) >> e) << e;
+    (b as u64, (t - b).min(u64::MAX as u128) as u64)
+}
+
+/// The alignment a base needs for [base, base + length) to be exact, where
+/// `length` is one returned by `round_bounds`.
+pub fn alignment(length: u64) -> u64 {
+    1 << exponent(0, length as u128)
+}
+
+/// Whether [base, base + length) is encodable without rounding. This is
+/// checked on the unsaturated top, so a length of u64::MAX, which would round
+/// up to 2^64, is not exact.
+pub fn exact(base: u64, length: u64) -> bool {
+    let top = base as u128 + length as u128;
+    let mask = (1u128 << exponent(base, top)) - 1;
+    base as u128 & mask == 0 && top & mask == 0
+}
+
+fn decode_bounds(e: u32, b: u64, t: u64, addr: u64) -> (u64, u64) {
+    let a_mid = ((addr as u128) >> e) as u64 & MASK;
+    let r = b.wrapping_sub(1 << (MW - 2)) & MASK;
+    let a_hi = ((addr as u128) >> (e + MW)) as i128;
+
+    // Whether x lies in the upper half of the region relative to the address.
+    let corr = |x: u64| -> i128 {
+        match (a_mid < r, x < r) {
+            (false, true) => 1,
+            (true, false) => -1,
+            _ => 0,
+        }
+    };
+
+    let base = ((a_hi + corr(b)) << (e + MW)) | ((b as i128) << e);
+    let top = ((a_hi + corr(t)) << (e + MW)) | ((t as i128) << e);
+
+    let base = base.clamp(0, u64::MAX as i128);
+    let top = top.clamp(base, 1i128 << 64);
+    (base as u64, (top - base).min(u64::MAX as i128) as u64)
+}
+
+/// Whether `c` with its cursor moved to `offset` still decodes to the same
+/// bounds. Capabilities whose bounds are not exactly encodable only allow
+/// in-bounds cursors.
+pub fn representable(c: &Capability, offset: u64) -> bool {
+    if !exact(c.base, c.length) {
+        return c.in_bounds(offset, 0);
+    }
+    let top = c.base as u128 + c.length as u128;
+    let e = exponent(c.base, top);
+    let b = (c.base >> e) & MASK;
+    let t = ((top >> e) as u64) & MASK;
+    decode_bounds(e, b, t, c.base.wrapping_add(offset)) == (c.base, c.length)
+}
+
+/// Encodes `c` into 16 bytes. The flag is false when the bounds, cursor or
+/// object type had to be approximated; such a capability must not be stored
+/// with its tag set.
+pub fn compress(c: &Capability) -> ([u8; 16], bool) {
+    let (base, length) = round_bounds(c.base, c.length);
+    let top = base as u128 + length as u128;
+    let e = exponent(base, top);
+    let b = (base >> e) & MASK;
+    let t = ((top >> e) as u64) & MASK;
+
+    let fits = exact(c.base, c.length)
+        && c.otype < (1 << OTYPE_BITS)
+        && representable(c, c.offset);
+
+    let meta = c.perms as u64
+        | (c.sealed as u64) << 8
+        | (c.otype & ((1 << OTYPE_BITS) - 1)) << 9
+        | (e as u64) << 25
+        | b << 31
+        | t << 47;
+
+    let mut out = [0u8; 16];
+    out[0..8].copy_from_slice(&c.get_address().to_le_bytes());
+    out[8..16].copy_from_slice(&meta.to_le_bytes());
+    (out, fits)
+}
+
+pub fn decompress(bytes: &[u8], tag: bool) -> Capability {
+    let rd_u64 = |offset: usize| -> u64 {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(&bytes[offset..offset + 8]);
+        u64::from_le_bytes(buf)
+    };
+
+    let addr = rd_u64(0);
+    let meta = rd_u64(8);
+
+    let e = ((meta >> 25) & 0x3F) as u32;
+    let b = (meta >> 31) & MASK;
+    let t = (meta >> 47) & MASK;
+    let (base, length) = decode_bounds(e, b, t, addr);
+
+    Capability {
+        base,
+        length,
+        offset: addr.wrapping_sub(base),
+        perms: meta as u8,
+        valid: tag,
+        sealed: (meta >> 8) & 1 != 0,
+        otype: (meta >> 9) & ((1 << OTYPE_BITS) - 1),
+    }
+}
@@ -11,7 +11,7 @@ pub fn generate(prog: &IRProgram) -> Result<Vec<u16>, String> {
             match inst {
                 IRInst::LoadImm(dst, val) => {
                     let rd = alloc.get(dst).unwrap();
-                    code.push(encode_addi(*rd, 0, *val as i8));
+                    code.extend(encode_li(*rd, *val));
                 }
                 IRInst::Add(dst, a, b) => {
                     let rd = alloc.get(dst).unwrap();
@@ -52,7 +52,7 @@ pub fn generate(prog: &IRProgram) -> Result<Vec<u16>, String> {
 }
 
 fn encode_add(rd: u8, rs1: u8, rs2: u8) -> u16 {
-    (0x0 << 12) | ((rd as u16) << 8) | ((rs1 as u16) << 4) | (rs2 as u16)
+    ((rd as u16) << 8) | ((rs1 as u16) << 4) | (rs2 as u16)
 }
 
 fn encode_sub(rd: u8, rs1: u8, rs2: u8) -> u16 {
@@ -68,7 +68,77 @@ fn encode_div(rd: u8, rs1: u8, rs2: u8) -> u16 {
 }
 
 fn encode_addi(rd: u8, rs1: u8, imm: i8) -> u16 {
-    (0x1 << 12) | ((rd as u16) << 8) | ((rs1 as u16) << 4) | ((imm as u8) as u16)
+    (0x1 << 12) | ((rd as u16) << 8) | ((rs1 as u16) << 4) | ((imm as u8 & 0xF) as u16)
+}
+
+const GROUP_BASE: u8 = 0x0;
+const GROUP_IMM: u8 = 0x1;
+
+// Loads a 64-bit constant in as few words as possible: addi from r0 when it
+// fits the nibble, otherwise the shortest of a prefixed lsi, a prefixed lzi,
+// or lui for the upper half followed by addi for the lower one.
+fn encode_li(rd: u8, v: i64) -> Vec<u16> {
+    if imm_size(v) == 0 {
+        return vec![encode_addi(rd, 0, v as i8)];
+    }
+
+    let mut candidates = vec![
+        encode_imm(GROUP_IMM, 0x0, rd, 0, v, imm_size(v)),
+        encode_imm(GROUP_IMM, 0x1, rd, 0, v, uimm_size(v)),
+    ];
+
+    let lo = v as i32 as i64;
+    let hi = ((v.wrapping_sub(lo) as u64) >> 32) as u32 as i32 as i64;
+    let mut seq = encode_imm(GROUP_IMM, 0x2, rd, 0, hi, imm_size(hi));
+    if lo != 0 {
+        seq.extend(encode_imm(GROUP_BASE, 0x1, rd, rd, lo, imm_size(lo)));
+    }
+    candidates.push(seq);
+
+    let mut best = candidates.remove(0);
+    for c in candidates {
+        if c.len() < best.len() {
+            best = c;
+        }
+    }
+    best
+}
+
+// Packs an instruction whose immediate needs `size` (0 = imm4, 1 = 16-bit,
+// 2 = 32-bit, 3 = 64-bit), adding the 0xB prefix word and the trailing
+// immediate words unless it is a plain base instruction.
+fn encode_imm(group: u8, op: u8, rd: u8, rs1: u8, v: i64, size: u8) -> Vec<u16> {
+    let mut out = Vec::new();
+    if group != GROUP_BASE || size != 0 {
+        out.push((0xB << 12) | ((group as u16) << 8) | ((size as u16) << 6));
+    }
+    let low = if size == 0 { (v as u16) & 0xF } else { 0 };
+    out.push(((op as u16) << 12) | ((rd as u16) << 8) | ((rs1 as u16) << 4) | low);
+    for k in 0..[0, 1, 2, 4][size as usize] {
+        out.push((v as u64 >> (16 * k)) as u16);
+    }
+    out
+}
+
+fn imm_size(v: i64) -> u8 {
+    if (-8..=7).contains(&v) {
+        0
+    } else if i16::try_from(v).is_ok() {
+        1
+    } else if i32::try_from(v).is_ok() {
+        2
+    } else {
+        3
+    }
+}
+
+fn uimm_size(v: i64) -> u8 {
+    match v as u64 {
+        0..=0xF => 0,
+        0x10..=0xFFFF => 1,
+        0x1_0000..=0xFFFF_FFFF => 2,
+        _ => 3,
+    }
 }
 
 fn encode_syscall(n: u8) -> u16 {
@@ -11,12 +11,11 @@ pub fn allocate_registers(instrs: &[IRInst]) -> HashMap<String, u8> {
             IRInst::Add(dst, _, _) |
             IRInst::Sub(dst, _, _) |
             IRInst::Mul(dst, _, _) |
-            IRInst::Div(dst, _, _) => {
-                if !alloc.contains_key(dst) {
+            IRInst::Div(dst, _, _)
+                if !alloc.contains_key(dst) => {
                     alloc.insert(dst.clone(), next_reg);
                     next_reg += 1;
                 }
-            }
             _ => {}
         }
     }
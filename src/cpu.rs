@@ -1,12 +1,82 @@
 use crate::cap::Capability;
 use crate::mem::Memory;
-use crate::trap::Trap;
+use crate::trap::{Trap, trap_cause};
+
+/// Capability register holding the stack; its cursor is the stack pointer.
+pub const STACK_CAP: usize = 7;
+
+/// Capability register that `call` and `call cN` leave the return capability
+/// in, and that `ret` jumps through.
+pub const LINK_CAP: usize = 6;
+
+/// Caller state saved by `cap.invoke` and restored by `cap.return`. It is kept
+/// inside the CPU rather than in guest memory so a callee cannot forge it.
+#[derive(Clone, Copy, Debug)]
+pub struct CallFrame {
+    pub pcc: Capability,
+    pub data: Capability,
+}
+
+/// Deepest `cap.invoke` nesting. An invoke beyond it faults rather than grow
+/// the frame stack without bound.
+pub const MAX_FRAMES: usize = 256;
+
+/// `TrapFrame::cap` for faults raised while fetching through the PCC.
+pub const PCC_INDEX: u8 = 8;
+
+/// `TrapFrame::cap` for traps that involve no capability.
+pub const NO_CAP: u8 = 0xFF;
+
+/// State saved when a trap is taken. Handlers read it with `trap.get`.
+#[derive(Clone, Copy, Debug)]
+pub struct TrapFrame {
+    /// PCC of the faulting instruction; `eret` resumes here.
+    pub epcc: Capability,
+    /// `trap_cause` of the trap.
+    pub cause: u64,
+    /// The faulting address for memory and fetch faults, the first words of
+    /// the instruction for illegal instructions, zero otherwise.
+    pub badaddr: u64,
+    /// Capability register that was at fault: c0-c7, PCC_INDEX or NO_CAP.
+    pub cap: u8,
+    /// Length in bytes of the faulting instruction, so a handler can skip it.
+    pub len: u64,
+    /// Set while a handler runs. A trap raised then goes to the host instead
+    /// of re-entering the handler.
+    pub active: bool,
+}
 
 pub struct CPU {
+    /// General-purpose registers. r0 always reads as zero.
     pub r: [u64; 16],
     pub c: [Capability; 8],
-    pub pc: u64,
+    /// Program counter capability. Its address is the PC and every fetch is
+    /// checked against it.
+    pub pcc: Capability,
     pub trap: Option<Trap>,
+    pub frames: Vec<CallFrame>,
+    /// Trap vector. When it holds a valid code capability with the system
+    /// permission, traps other than supervisor syscalls jump to it instead
+    /// of stopping the CPU.
+    pub tvec: Capability,
+    pub tf: TrapFrame,
+    /// Root capability over all of memory and the device region, readable
+    /// only in supervisor mode with `cap.getroot`.
+    pub root: Capability,
+    /// Steps taken, one per instruction attempted or interrupt taken. Time on
+    /// this machine is measured in these, so runs are reproducible.
+    pub cycle: u64,
+    /// Instructions retired. Faulting instructions do not count.
+    pub instret: u64,
+    /// The timer interrupt is pending while `cycle >= timecmp`; set with
+    /// `timer.set`. u64::MAX disables it.
+    pub timecmp: u64,
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CPU {
@@ -14,13 +84,50 @@ impl CPU {
         CPU {
             r: [0; 16],
             c: [Capability::null(); 8],
-            pc: 0,
+            pcc: Capability::null(),
             trap: None,
+            frames: Vec::new(),
+            tvec: Capability::null(),
+            tf: TrapFrame {
+                epcc: Capability::null(),
+                cause: 0,
+                badaddr: 0,
+                cap: NO_CAP,
+                len: 0,
+                active: false,
+            },
+            root: Capability::null(),
+            cycle: 0,
+            instret: 0,
+            timecmp: u64::MAX,
         }
     }
 
+    pub fn pc(&self) -> u64 {
+        self.pcc.get_address()
+    }
+
+    /// Whether the CPU is in supervisor mode, i.e. the PCC carries the system
+    /// permission. Supervisor code drops to user mode by jumping through a
+    /// capability without it and only comes back through a trap.
+    pub fn supervisor(&self) -> bool {
+        self.pcc.can_access_system()
+    }
+
+    /// Moves the PC within the current PCC.
+    pub fn set_pc(&mut self, pc: u64) {
+        self.pcc.offset = pc.wrapping_sub(self.pcc.base);
+    }
+
     pub fn raise_trap(&mut self, t: Trap) {
+        self.fault(t, 0, NO_CAP);
+    }
+
+    /// Raises `t` for an access to `addr` through capability register `cap`.
+    pub fn fault(&mut self, t: Trap, addr: u64, cap: u8) {
         self.trap = Some(t);
+        self.tf.badaddr = addr;
+        self.tf.cap = cap;
     }
 
     pub fn is_trapped(&self) -> bool {
@@ -32,16 +139,126 @@ impl CPU {
             return;
         }
 
-        let inst = match mem.fetch16(self.pc, &self.c[1]) {
+        let now = self.cycle;
+        self.cycle = now.wrapping_add(1);
+
+        // Interrupts are taken between instructions, and only when a handler
+        // can receive them.
+        if now >= self.timecmp && self.handler_ready() {
+            self.tf = TrapFrame {
+                epcc: self.pcc,
+                cause: trap_cause(Trap::TimerInterrupt),
+                badaddr: 0,
+                cap: NO_CAP,
+                len: 0,
+                active: true,
+            };
+            self.pcc = self.tvec;
+            return;
+        }
+
+        let epcc = self.pcc;
+        let (len, raw) = self.fetch_execute(mem);
+
+        // r0 is hardwired to zero: writes to it are discarded.
+        self.r[0] = 0;
+
+        match self.trap {
+            None | Some(Trap::Syscall(_)) => self.instret = self.instret.wrapping_add(1),
+            _ => {}
+        }
+
+        if let Some(t) = self.trap {
+            self.enter_trap(t, epcc, len, raw);
+        }
+    }
+
+    /// Steps until `cycle` reaches `until` or the CPU stops on a trap the host
+    /// has to handle, and returns the number of steps taken. Together with
+    /// `timecmp` this lets tests preempt a program at an exact instruction.
+    pub fn run_until(&mut self, mem: &mut Memory, until: u64) -> u64 {
+        let start = self.cycle;
+        while self.cycle < until && !self.is_trapped() {
+            self.step(mem);
+        }
+        self.cycle - start
+    }
+
+    // The vector must be supervisor code; cap.settvec refuses anything else,
+    // but tvec is also writable from the host.
+    fn handler_ready(&self) -> bool {
+        let v = self.tvec;
+        !self.tf.active && v.valid && !v.sealed && v.can_exec() && v.can_access_system()
+    }
+
+    // Runs one instruction and returns its length and up to four of its
+    // words, packed little-endian, for the trap frame.
+    fn fetch_execute(&mut self, mem: &mut Memory) -> (u64, u64) {
+        let pc = self.pc();
+        let first = match mem.fetch16(pc, &self.pcc) {
             Ok(v) => v,
+            Err(t) => {
+                self.fault(t, pc, PCC_INDEX);
+                return (0, 0);
+            }
+        };
+
+        let len = crate::decode::inst_len(first);
+        let words = &mut [first; 6][..(len / 2) as usize];
+        let mut addr = pc;
+        for w in words.iter_mut().skip(1) {
+            addr = addr.wrapping_add(2);
+            match mem.fetch16(addr, &self.pcc) {
+                Ok(v) => *w = v,
+                Err(t) => {
+                    self.fault(t, addr, PCC_INDEX);
+                    return (0, 0);
+                }
+            }
+        }
+
+        let raw = words.iter().take(4).enumerate()
+            .fold(0u64, |acc, (k, w)| acc | (*w as u64) << (16 * k));
+
+        let inst = match crate::decode::decode(words) {
+            Ok(i) => i,
             Err(t) => {
                 self.raise_trap(t);
-                return;
+                return (len, raw);
             }
         };
 
-        self.pc = self.pc.wrapping_add(2);
+        self.set_pc(pc.wrapping_add(len));
 
-        crate::exec::execute(self, mem, inst);
+        crate::exec::execute(self, mem, &inst);
+        (len, raw)
+    }
+
+    // Fills in the trap frame and, if a handler is installed and not already
+    // running, hands the trap to it. Syscalls made in supervisor mode go to
+    // the host and do not touch the frame, so a handler may make them; user
+    // syscalls go to the handler like any other trap.
+    fn enter_trap(&mut self, t: Trap, epcc: Capability, len: u64, raw: u64) {
+        if matches!(t, Trap::Syscall(_)) && epcc.can_access_system() {
+            return;
+        }
+
+        self.tf.epcc = epcc;
+        self.tf.cause = trap_cause(t);
+        self.tf.len = len;
+        match t {
+            Trap::IllegalInstruction => self.tf.badaddr = raw,
+            Trap::Syscall(_) => {
+                self.tf.badaddr = 0;
+                self.tf.cap = NO_CAP;
+            }
+            _ => {}
+        }
+
+        if self.handler_ready() {
+            self.tf.active = true;
+            self.pcc = self.tvec;
+            self.trap = None;
+        }
     }
 }
@@ -1,13 +1,79 @@
 // emulator/src/decode.rs
-use crate::isa::Inst;
+use crate::isa::{Inst, OP_EXT, GROUP_BASE};
+use crate::trap::Trap;
 
-pub fn decode(raw: u16) -> Inst {
+const RET_WORD: u16 = (OP_EXT as u16) << 12;
+
+fn is_prefix(word: u16) -> bool {
+    (word >> 12) as u8 == OP_EXT && word != RET_WORD
+}
+
+fn imm_words(prefix: u16) -> u64 {
+    match (prefix >> 6) & 0x3 {
+        0 => 0,
+        1 => 1,
+        2 => 2,
+        _ => 4,
+    }
+}
+
+/// Size in bytes of the instruction starting with `first`.
+pub fn inst_len(first: u16) -> u64 {
+    if is_prefix(first) {
+        4 + imm_words(first) * 2
+    } else {
+        2
+    }
+}
+
+/// Decodes one instruction from the `inst_len(words[0]) / 2` words it spans.
+/// Fewer words than that, as from a prefix cut off at the end of the code, is
+/// an illegal instruction.
+pub fn decode(words: &[u16]) -> Result<Inst, Trap> {
+    let first = *words.first().ok_or(Trap::IllegalInstruction)?;
+    if (words.len() as u64) * 2 < inst_len(first) {
+        return Err(Trap::IllegalInstruction);
+    }
+
+    if !is_prefix(first) {
+        return Ok(decode_word(first, 0, 2));
+    }
+
+    if first & 0x3F != 0 {
+        return Err(Trap::IllegalInstruction);
+    }
+
+    let group = ((first >> 8) & 0xF) as u8;
+    let mut inst = decode_word(words[1], group, inst_len(first));
+
+    // Prefixes do not nest. Other groups have their own opcode maps, where
+    // 0xB is an ordinary opcode.
+    if group == GROUP_BASE && inst.opcode == OP_EXT {
+        return Err(Trap::IllegalInstruction);
+    }
+
+    let n = imm_words(first) as usize;
+    if n > 0 {
+        let mut raw = 0u64;
+        for (k, w) in words[2..2 + n].iter().enumerate() {
+            raw |= (*w as u64) << (16 * k);
+        }
+        let shift = 64 - 16 * n as u32;
+        inst.imm = ((raw << shift) as i64) >> shift;
+    }
+
+    Ok(inst)
+}
+
+fn decode_word(raw: u16, group: u8, len: u64) -> Inst {
     let imm4 = (raw & 0xF) as u8;
     Inst {
+        group,
         opcode: ((raw >> 12) & 0xF) as u8,
         rd:     ((raw >>  8) & 0xF) as u8,
         rs1:    ((raw >>  4) & 0xF) as u8,
         rs2:    imm4,  // Low 4 bits are either rs2 or immediate
-        imm:    (imm4 as i8) << 4 >> 4,   // Sign-extend 4-bit to 8-bit
+        imm:    ((imm4 as i8) << 4 >> 4) as i64,   // Sign-extend 4-bit
+        len,
     }
-}
\ No newline at end of file
+}
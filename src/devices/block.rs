@@ -0,0 +1,228 @@
+// Block device.
+//
+//     0x00  sector   write: sector number for the next command
+//     0x08  buffer   write: RAM address of a capability naming the buffer
+//     0x10  command  write: CMD_READ or CMD_WRITE, performed immediately
+//     0x18  status   read:  result of the last command
+//     0x20  sectors  read:  capacity in sectors
+//
+// Transfers go through the buffer capability, which the guest stores in RAM
+// with cap.store and passes by address. It must be tagged and cover
+// SECTOR_SIZE bytes from its cursor with the permission the transfer needs,
+// so the device can only touch memory the guest could have touched itself.
+
+use crate::bus::Device;
+use crate::cap::{Capability, CAP_SIZE};
+use crate::mem::Memory;
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+pub const SECTOR_SIZE: usize = 512;
+
+pub const SECTOR: u64 = 0x00;
+pub const BUFFER: u64 = 0x08;
+pub const COMMAND: u64 = 0x10;
+pub const STATUS: u64 = 0x18;
+pub const SECTORS: u64 = 0x20;
+
+pub const CMD_READ: u64 = 1;
+pub const CMD_WRITE: u64 = 2;
+
+pub const STATUS_OK: u64 = 0;
+/// The sector is past the end of the disk or the host I/O failed.
+pub const STATUS_IO_ERROR: u64 = 1;
+/// The buffer capability is missing, too small or lacks permission.
+pub const STATUS_CAP_FAULT: u64 = 2;
+pub const STATUS_BAD_COMMAND: u64 = 3;
+
+/// Storage behind a block device.
+pub trait Disk {
+    fn sectors(&self) -> u64;
+    fn read_sector(&mut self, n: u64, buf: &mut [u8; SECTOR_SIZE]) -> bool;
+    fn write_sector(&mut self, n: u64, buf: &[u8; SECTOR_SIZE]) -> bool;
+}
+
+pub struct BlockDevice {
+    disk: Box<dyn Disk>,
+    sector: u64,
+    buffer: u64,
+    status: u64,
+    // Command written but not yet carried out by dma().
+    pending: Option<u64>,
+}
+
+impl BlockDevice {
+    pub fn new(disk: Box<dyn Disk>) -> Self {
+        BlockDevice { disk, sector: 0, buffer: 0, status: STATUS_OK, pending: None }
+    }
+
+    fn buffer_cap(&self, mem: &Memory) -> Option<Capability> {
+        // The device reads the descriptor with its own view of RAM; only the
+        // tag decides whether what it finds there is a capability.
+        let ram = Capability {
+            base: 0,
+            length: mem.bytes.len() as u64,
+            offset: 0,
+            perms: 0x9,
+            valid: true,
+            sealed: false,
+            otype: 0,
+        };
+        if !self.buffer.is_multiple_of(CAP_SIZE) {
+            return None;
+        }
+        let cap = mem.load_cap(self.buffer, &ram).ok()?;
+        (cap.valid && !cap.sealed && cap.in_bounds(cap.offset, SECTOR_SIZE as u64)).then_some(cap)
+    }
+
+    fn run(&mut self, cmd: u64, mem: &mut Memory) -> u64 {
+        if cmd != CMD_READ && cmd != CMD_WRITE {
+            return STATUS_BAD_COMMAND;
+        }
+        let Some(cap) = self.buffer_cap(mem) else {
+            return STATUS_CAP_FAULT;
+        };
+        if self.sector >= self.disk.sectors() {
+            return STATUS_IO_ERROR;
+        }
+
+        // A tagged capability never reaches past 2^64, so the checked_adds
+        // below cannot fail today; they only stop a wrap if that changes.
+        let addr = cap.get_address();
+        let mut buf = [0u8; SECTOR_SIZE];
+
+        if cmd == CMD_READ {
+            if !cap.can_write() {
+                return STATUS_CAP_FAULT;
+            }
+            if !self.disk.read_sector(self.sector, &mut buf) {
+                return STATUS_IO_ERROR;
+            }
+            for (k, b) in buf.iter().enumerate() {
+                let Some(a) = addr.checked_add(k as u64) else {
+                    return STATUS_CAP_FAULT;
+                };
+                if mem.store8(a, *b, &cap).is_err() {
+                    return STATUS_CAP_FAULT;
+                }
+            }
+        } else {
+            for (k, b) in buf.iter_mut().enumerate() {
+                let Some(a) = addr.checked_add(k as u64) else {
+                    return STATUS_CAP_FAULT;
+                };
+                match mem.load8(a, &cap) {
+                    Ok(v) => *b = v,
+                    Err(_) => return STATUS_CAP_FAULT,
+                }
+            }
+            if !self.disk.write_sector(self.sector, &buf) {
+                return STATUS_IO_ERROR;
+            }
+        }
+        STATUS_OK
+    }
+}
+
+impl Device for BlockDevice {
+    fn read(&mut self, offset: u64, _size: u64) -> u64 {
+        match offset {
+            SECTOR => self.sector,
+            BUFFER => self.buffer,
+            STATUS => self.status,
+            SECTORS => self.disk.sectors(),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u64, _size: u64, val: u64) {
+        match offset {
+            SECTOR => self.sector = val,
+            BUFFER => self.buffer = val,
+            COMMAND => self.pending = Some(val),
+            _ => {}
+        }
+    }
+
+    fn dma(&mut self, mem: &mut Memory) {
+        if let Some(cmd) = self.pending.take() {
+            self.status = self.run(cmd, mem);
+        }
+    }
+}
+
+/// A disk image file on the host. Its size is rounded down to whole sectors.
+pub struct FileDisk {
+    file: File,
+    sectors: u64,
+}
+
+impl FileDisk {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let file = OpenOptions::new().read(true).write(true).open(path)
+            .map_err(|e| format!("cannot open {}: {}", path, e))?;
+        let len = file.metadata().map_err(|e| format!("cannot stat {}: {}", path, e))?.len();
+        Ok(FileDisk { file, sectors: len / SECTOR_SIZE as u64 })
+    }
+
+    fn seek(&mut self, n: u64) -> bool {
+        self.file.seek(SeekFrom::Start(n * SECTOR_SIZE as u64)).is_ok()
+    }
+}
+
+impl Disk for FileDisk {
+    fn sectors(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8; SECTOR_SIZE]) -> bool {
+        self.seek(n) && self.file.read_exact(buf).is_ok()
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8; SECTOR_SIZE]) -> bool {
+        self.seek(n) && self.file.write_all(buf).is_ok()
+    }
+}
+
+/// An in-memory disk. Clones share the same contents, so a test can inspect
+/// what the guest wrote.
+#[derive(Clone)]
+pub struct MemDisk {
+    data: Rc<RefCell<Vec<u8>>>,
+}
+
+impl MemDisk {
+    pub fn new(sectors: u64) -> Self {
+        MemDisk { data: Rc::new(RefCell::new(vec![0; sectors as usize * SECTOR_SIZE])) }
+    }
+
+    pub fn sector(&self, n: u64) -> Vec<u8> {
+        let a = n as usize * SECTOR_SIZE;
+        self.data.borrow()[a..a + SECTOR_SIZE].to_vec()
+    }
+
+    pub fn set_sector(&self, n: u64, bytes: &[u8]) {
+        let a = n as usize * SECTOR_SIZE;
+        self.data.borrow_mut()[a..a + bytes.len()].copy_from_slice(bytes);
+    }
+}
+
+impl Disk for MemDisk {
+    fn sectors(&self) -> u64 {
+        (self.data.borrow().len() / SECTOR_SIZE) as u64
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8; SECTOR_SIZE]) -> bool {
+        let a = n as usize * SECTOR_SIZE;
+        buf.copy_from_slice(&self.data.borrow()[a..a + SECTOR_SIZE]);
+        true
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8; SECTOR_SIZE]) -> bool {
+        let a = n as usize * SECTOR_SIZE;
+        self.data.borrow_mut()[a..a + SECTOR_SIZE].copy_from_slice(buf);
+        true
+    }
+}
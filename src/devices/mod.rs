@@ -0,0 +1,16 @@
+// Peripherals for the memory-mapped I/O region, which starts above RAM. Each
+// device gets a 4 KiB page.
+
+pub mod block;
+pub mod rng;
+pub mod uart;
+
+/// Start of the device region.
+pub const MMIO_BASE: u64 = 0x1000_0000;
+
+/// Size of each device's window.
+pub const DEVICE_SIZE: u64 = 0x1000;
+
+pub const UART_BASE: u64 = MMIO_BASE;
+pub const RNG_BASE: u64 = MMIO_BASE + 0x1000;
+pub const BLOCK_BASE: u64 = MMIO_BASE + 0x2000;
@@ -0,0 +1,37 @@
+// Pseudo-random number source.
+//
+//     0x0  value  read: next 64-bit number (narrow reads take the low bytes)
+//                 write: reseed
+//
+// It is deterministic for a given seed so that guest runs can be replayed.
+
+use crate::bus::Device;
+
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed.max(1) }
+    }
+}
+
+impl Device for Rng {
+    fn read(&mut self, offset: u64, size: u64) -> u64 {
+        if offset != 0 {
+            return 0;
+        }
+        // xorshift64
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        if size >= 8 { self.state } else { self.state & ((1 << (8 * size)) - 1) }
+    }
+
+    fn write(&mut self, offset: u64, _size: u64, val: u64) {
+        if offset == 0 {
+            self.state = val.max(1);
+        }
+    }
+}
@@ -0,0 +1,230 @@
+// Serial port.
+//
+//     0x0  data    write: transmit the low byte
+//                  read:  next received byte, 0 when none is waiting
+//     0x4  status  bit 0: a received byte is waiting
+//                  bit 1: ready to transmit (always set)
+//                  bit 2: input is closed and drained
+//
+// The other end is a `SerialPort`: the host terminal or, in tests, a pair of
+// in-memory buffers.
+
+use crate::bus::Device;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+pub const DATA: u64 = 0x0;
+pub const STATUS: u64 = 0x4;
+
+pub const RX_READY: u64 = 1;
+pub const TX_READY: u64 = 2;
+pub const RX_CLOSED: u64 = 4;
+
+pub trait SerialPort {
+    fn send(&mut self, byte: u8);
+    /// The next input byte, if one has arrived. Never blocks.
+    fn recv(&mut self) -> Option<u8>;
+    /// Whether no more input will ever arrive.
+    fn closed(&self) -> bool {
+        false
+    }
+}
+
+pub struct Uart {
+    port: Box<dyn SerialPort>,
+    // A byte already taken from the port by a status read.
+    rx: Option<u8>,
+}
+
+impl Uart {
+    pub fn new(port: Box<dyn SerialPort>) -> Self {
+        Uart { port, rx: None }
+    }
+
+    fn poll(&mut self) -> Option<u8> {
+        if self.rx.is_none() {
+            self.rx = self.port.recv();
+        }
+        self.rx
+    }
+}
+
+impl Device for Uart {
+    fn read(&mut self, offset: u64, _size: u64) -> u64 {
+        match offset {
+            DATA => {
+                let b = self.poll();
+                self.rx = None;
+                b.unwrap_or(0) as u64
+            }
+            STATUS => {
+                let mut s = TX_READY;
+                if self.poll().is_some() {
+                    s |= RX_READY;
+                } else if self.port.closed() {
+                    s |= RX_CLOSED;
+                }
+                s
+            }
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u64, _size: u64, val: u64) {
+        if offset == DATA {
+            self.port.send(val as u8);
+        }
+    }
+}
+
+/// The host's stdin and stdout. Stdin is read on a background thread, started
+/// on first use, so the guest can poll without blocking the emulator.
+///
+/// The guest may also read fd 0 through the syscall layer. Give that the
+/// `HostStdin` from `stdin()` so both draw from the one reader thread rather
+/// than racing each other for bytes.
+#[derive(Default)]
+pub struct HostSerial {
+    input: Rc<RefCell<HostInput>>,
+}
+
+#[derive(Default)]
+struct HostInput {
+    rx: Option<Receiver<u8>>,
+    closed: bool,
+}
+
+impl HostInput {
+    fn receiver(&mut self) -> &Receiver<u8> {
+        self.rx.get_or_insert_with(|| {
+            let (tx, rx) = mpsc::channel();
+            std::thread::spawn(move || {
+                for b in std::io::stdin().lock().bytes() {
+                    match b {
+                        Ok(b) if tx.send(b).is_ok() => {}
+                        _ => break,
+                    }
+                }
+            });
+            rx
+        })
+    }
+}
+
+impl HostSerial {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A blocking reader over the same input the UART receives.
+    pub fn stdin(&self) -> HostStdin {
+        HostStdin { input: self.input.clone() }
+    }
+}
+
+impl SerialPort for HostSerial {
+    fn send(&mut self, byte: u8) {
+        let mut out = std::io::stdout();
+        let _ = out.write_all(&[byte]);
+        let _ = out.flush();
+    }
+
+    fn recv(&mut self) -> Option<u8> {
+        let mut input = self.input.borrow_mut();
+        match input.receiver().try_recv() {
+            Ok(b) => Some(b),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                input.closed = true;
+                None
+            }
+        }
+    }
+
+    fn closed(&self) -> bool {
+        self.input.borrow().closed
+    }
+}
+
+/// Host stdin shared with a `HostSerial`. Reads block until at least one byte
+/// arrives, then return whatever else is already waiting.
+pub struct HostStdin {
+    input: Rc<RefCell<HostInput>>,
+}
+
+impl Read for HostStdin {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut input = self.input.borrow_mut();
+        if buf.is_empty() || input.closed {
+            return Ok(0);
+        }
+        let rx = input.receiver();
+        let Ok(first) = rx.recv() else {
+            input.closed = true;
+            return Ok(0);
+        };
+        buf[0] = first;
+        let mut n = 1;
+        while n < buf.len() {
+            match rx.try_recv() {
+                Ok(b) => {
+                    buf[n] = b;
+                    n += 1;
+                }
+                Err(_) => break,
+            }
+        }
+        Ok(n)
+    }
+}
+
+/// In-memory serial line. Clones share the same buffers, so a test keeps one
+/// to feed input and inspect output after handing the other to the UART.
+#[derive(Clone, Default)]
+pub struct BufferSerial {
+    inner: Rc<RefCell<Buffers>>,
+}
+
+#[derive(Default)]
+struct Buffers {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+    closed: bool,
+}
+
+impl BufferSerial {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_input(&self, bytes: &[u8]) {
+        self.inner.borrow_mut().input.extend(bytes);
+    }
+
+    /// Marks the input as finished; the guest sees RX_CLOSED once drained.
+    pub fn close_input(&self) {
+        self.inner.borrow_mut().closed = true;
+    }
+
+    pub fn output(&self) -> Vec<u8> {
+        self.inner.borrow().output.clone()
+    }
+}
+
+impl SerialPort for BufferSerial {
+    fn send(&mut self, byte: u8) {
+        self.inner.borrow_mut().output.push(byte);
+    }
+
+    fn recv(&mut self) -> Option<u8> {
+        self.inner.borrow_mut().input.pop_front()
+    }
+
+    fn closed(&self) -> bool {
+        let b = self.inner.borrow();
+        b.closed && b.input.is_empty()
+    }
+}
@@ -1,27 +1,96 @@
-use crate::{cpu::CPU, mem::Memory, decode, isa::Inst};
+use crate::{cpu::{CPU, CallFrame, LINK_CAP, MAX_FRAMES, STACK_CAP}, mem::Memory};
+use crate::isa::*;
 use crate::cap::Capability;
+use crate::cap_compressed;
 use crate::trap::Trap;
 
-pub fn execute(cpu: &mut CPU, mem: &mut Memory, raw: u16) {
-    let inst = decode::decode(raw);
-
-    match inst.opcode {
-        0x0 => op_add(cpu, &inst),
-        0x1 => op_addi(cpu, &inst),
-        0x2 => op_div(cpu, &inst),
-        0x3 => op_sub(cpu, &inst),
-        0x4 => op_mul(cpu, &inst),
-        0x5 => op_ld(cpu, mem, &inst),
-        0x6 => op_st(cpu, mem, &inst),
-        0x7 => op_br(cpu, &inst),
-        0x8 => op_brz(cpu, &inst),
-        0x9 => op_jmp(cpu, &inst),
-        0xA => op_call(cpu, &inst),
-        0xB => op_ret(cpu, &inst),
-        0xC => op_syscall(cpu, &inst),
-        0xD => cap_null(cpu, &inst),
-        0xE => cap_copy(cpu, &inst),
-        0xF => cap_offset(cpu, &inst),
+pub fn execute(cpu: &mut CPU, mem: &mut Memory, inst: &Inst) {
+    // The trap group manages the trap vector and system registers. It holds
+    // every supervisor-only instruction, so this one check gates them all.
+    if inst.group == GROUP_TRAP && !cpu.supervisor() {
+        cpu.raise_trap(Trap::PrivilegeViolation);
+        return;
+    }
+
+    match (inst.group, inst.opcode) {
+        (GROUP_BASE, 0x0) => op_add(cpu, inst),
+        (GROUP_BASE, 0x1) => op_addi(cpu, inst),
+        (GROUP_BASE, 0x2) => op_div(cpu, inst),
+        (GROUP_BASE, 0x3) => op_sub(cpu, inst),
+        (GROUP_BASE, 0x4) => op_mul(cpu, inst),
+        (GROUP_BASE, 0x5) => op_ld(cpu, mem, inst),
+        (GROUP_BASE, 0x6) => op_st(cpu, mem, inst),
+        (GROUP_BASE, 0x7) => op_br(cpu, inst),
+        (GROUP_BASE, 0x8) => op_brz(cpu, inst),
+        (GROUP_BASE, 0x9) => op_jmp(cpu, inst),
+        (GROUP_BASE, 0xA) => op_call(cpu, inst),
+        (GROUP_BASE, 0xB) => op_ret(cpu, inst),
+        (GROUP_BASE, 0xC) => op_syscall(cpu, inst),
+        (GROUP_BASE, 0xD) => cap_null(cpu, inst),
+        (GROUP_BASE, 0xE) => cap_copy(cpu, inst),
+        (GROUP_BASE, 0xF) => cap_offset(cpu, inst),
+        (GROUP_IMM, 0x0) => op_lsi(cpu, inst),
+        (GROUP_IMM, 0x1) => op_lzi(cpu, inst),
+        (GROUP_IMM, 0x2) => op_lui(cpu, inst),
+        (GROUP_ALU, 0x0) => op_and(cpu, inst),
+        (GROUP_ALU, 0x1) => op_or(cpu, inst),
+        (GROUP_ALU, 0x2) => op_xor(cpu, inst),
+        (GROUP_ALU, 0x3) => op_not(cpu, inst),
+        (GROUP_ALU, 0x4) => op_shl(cpu, inst),
+        (GROUP_ALU, 0x5) => op_shr(cpu, inst),
+        (GROUP_ALU, 0x6) => op_sar(cpu, inst),
+        (GROUP_ALU, 0x7) => op_divs(cpu, inst),
+        (GROUP_ALU, 0x8) => op_remu(cpu, inst),
+        (GROUP_ALU, 0x9) => op_rems(cpu, inst),
+        (GROUP_ALU, 0xA) => op_slt(cpu, inst),
+        (GROUP_ALU, 0xB) => op_sltu(cpu, inst),
+        (GROUP_BRANCH, 0x0) => op_bne(cpu, inst),
+        (GROUP_BRANCH, 0x1) => op_blt(cpu, inst),
+        (GROUP_BRANCH, 0x2) => op_bge(cpu, inst),
+        (GROUP_BRANCH, 0x3) => op_bltu(cpu, inst),
+        (GROUP_BRANCH, 0x4) => op_bgeu(cpu, inst),
+        (GROUP_MEM | GROUP_CMEM, 0x0) => op_load(cpu, mem, inst, 1, true),
+        (GROUP_MEM | GROUP_CMEM, 0x1) => op_load(cpu, mem, inst, 1, false),
+        (GROUP_MEM | GROUP_CMEM, 0x2) => op_load(cpu, mem, inst, 2, true),
+        (GROUP_MEM | GROUP_CMEM, 0x3) => op_load(cpu, mem, inst, 2, false),
+        (GROUP_MEM | GROUP_CMEM, 0x4) => op_load(cpu, mem, inst, 4, true),
+        (GROUP_MEM | GROUP_CMEM, 0x5) => op_load(cpu, mem, inst, 4, false),
+        (GROUP_MEM | GROUP_CMEM, 0x6) => op_load(cpu, mem, inst, 8, false),
+        (GROUP_MEM | GROUP_CMEM, 0x7) => op_store(cpu, mem, inst, 1),
+        (GROUP_MEM | GROUP_CMEM, 0x8) => op_store(cpu, mem, inst, 2),
+        (GROUP_MEM | GROUP_CMEM, 0x9) => op_store(cpu, mem, inst, 4),
+        (GROUP_MEM | GROUP_CMEM, 0xA) => op_store(cpu, mem, inst, 8),
+        (GROUP_MEM | GROUP_CMEM, 0xB) => cap_load(cpu, mem, inst),
+        (GROUP_MEM | GROUP_CMEM, 0xC) => cap_store(cpu, mem, inst),
+        (GROUP_STACK, 0x0) => op_push(cpu, mem, inst),
+        (GROUP_STACK, 0x1) => op_pop(cpu, mem, inst),
+        (GROUP_STACK, 0x2) => op_pcall(cpu, mem, inst),
+        (GROUP_STACK, 0x3) => op_pret(cpu, mem, inst),
+        (GROUP_CAP, 0x0) => cap_setbounds(cpu, inst),
+        (GROUP_CAP, 0x1) => cap_andperm(cpu, inst),
+        (GROUP_CAP, 0x2) => cap_seal(cpu, inst),
+        (GROUP_CAP, 0x3) => cap_unseal(cpu, inst),
+        (GROUP_CAP, 0x4) => cap_invoke(cpu, inst),
+        (GROUP_CAP, 0x5) => cap_return(cpu, inst),
+        (GROUP_CAP, 0x6) => cap_get(cpu, inst, |c| c.base),
+        (GROUP_CAP, 0x7) => cap_get(cpu, inst, |c| c.length),
+        (GROUP_CAP, 0x8) => cap_get(cpu, inst, |c| c.offset),
+        (GROUP_CAP, 0x9) => cap_get(cpu, inst, |c| c.perms as u64),
+        (GROUP_CAP, 0xA) => cap_get(cpu, inst, |c| c.valid as u64),
+        (GROUP_CAP, 0xB) => cap_get(cpu, inst, |c| c.get_address()),
+        (GROUP_CAP, 0xC) => cap_get(cpu, inst, |c| c.sealed as u64),
+        (GROUP_CAP, 0xD) => cap_get(cpu, inst, |c| c.otype),
+        (GROUP_CAP, 0xE) => cap_jmp(cpu, inst),
+        (GROUP_CAP, 0xF) => cap_call(cpu, inst),
+        (GROUP_TRAP, 0x0) => op_eret(cpu, inst),
+        (GROUP_TRAP, 0x1) => trap_get(cpu, inst),
+        (GROUP_TRAP, 0x2) => trap_setepc(cpu, inst),
+        (GROUP_TRAP, 0x3) => cap_settvec(cpu, inst),
+        (GROUP_TRAP, 0x4) => cap_gettvec(cpu, inst),
+        (GROUP_TRAP, 0x5) => cap_getroot(cpu, inst),
+        (GROUP_TRAP, 0x6) => timer_set(cpu, inst),
+        (GROUP_COUNTER, 0x0) => cpu.r[inst.rd as usize] = cpu.cycle,
+        (GROUP_COUNTER, 0x1) => cpu.r[inst.rd as usize] = cpu.instret,
         _ => cpu.raise_trap(Trap::IllegalInstruction),
     }
 }
@@ -31,7 +100,19 @@ fn op_add(cpu: &mut CPU, i: &Inst) {
 }
 
 fn op_addi(cpu: &mut CPU, i: &Inst) {
-    cpu.r[i.rd as usize] = cpu.r[i.rs1 as usize].wrapping_add(i.imm as i64 as u64);
+    cpu.r[i.rd as usize] = cpu.r[i.rs1 as usize].wrapping_add(i.imm as u64);
+}
+
+fn op_lsi(cpu: &mut CPU, i: &Inst) {
+    cpu.r[i.rd as usize] = i.imm as u64;
+}
+
+fn op_lzi(cpu: &mut CPU, i: &Inst) {
+    cpu.r[i.rd as usize] = i.uimm();
+}
+
+fn op_lui(cpu: &mut CPU, i: &Inst) {
+    cpu.r[i.rd as usize] = (i.imm as u64) << 32;
 }
 
 fn op_div(cpu: &mut CPU, i: &Inst) {
@@ -44,6 +125,46 @@ fn op_div(cpu: &mut CPU, i: &Inst) {
     cpu.r[i.rd as usize] = a / b;
 }
 
+// Signed division truncates toward zero. i64::MIN / -1 wraps to i64::MIN
+// (and its remainder is 0) rather than trapping.
+fn op_divs(cpu: &mut CPU, i: &Inst) {
+    let a = cpu.r[i.rs1 as usize] as i64;
+    let b = cpu.r[i.rs2 as usize] as i64;
+    if b == 0 {
+        cpu.raise_trap(Trap::DivideByZero);
+        return;
+    }
+    cpu.r[i.rd as usize] = a.wrapping_div(b) as u64;
+}
+
+fn op_remu(cpu: &mut CPU, i: &Inst) {
+    let a = cpu.r[i.rs1 as usize];
+    let b = cpu.r[i.rs2 as usize];
+    if b == 0 {
+        cpu.raise_trap(Trap::DivideByZero);
+        return;
+    }
+    cpu.r[i.rd as usize] = a % b;
+}
+
+fn op_rems(cpu: &mut CPU, i: &Inst) {
+    let a = cpu.r[i.rs1 as usize] as i64;
+    let b = cpu.r[i.rs2 as usize] as i64;
+    if b == 0 {
+        cpu.raise_trap(Trap::DivideByZero);
+        return;
+    }
+    cpu.r[i.rd as usize] = a.wrapping_rem(b) as u64;
+}
+
+fn op_slt(cpu: &mut CPU, i: &Inst) {
+    cpu.r[i.rd as usize] = ((cpu.r[i.rs1 as usize] as i64) < (cpu.r[i.rs2 as usize] as i64)) as u64;
+}
+
+fn op_sltu(cpu: &mut CPU, i: &Inst) {
+    cpu.r[i.rd as usize] = (cpu.r[i.rs1 as usize] < cpu.r[i.rs2 as usize]) as u64;
+}
+
 fn op_sub(cpu: &mut CPU, i: &Inst) {
     cpu.r[i.rd as usize] = cpu.r[i.rs1 as usize].wrapping_sub(cpu.r[i.rs2 as usize]);
 }
@@ -52,93 +173,646 @@ fn op_mul(cpu: &mut CPU, i: &Inst) {
     cpu.r[i.rd as usize] = cpu.r[i.rs1 as usize].wrapping_mul(cpu.r[i.rs2 as usize]);
 }
 
+fn op_and(cpu: &mut CPU, i: &Inst) {
+    cpu.r[i.rd as usize] = cpu.r[i.rs1 as usize] & cpu.r[i.rs2 as usize];
+}
+
+fn op_or(cpu: &mut CPU, i: &Inst) {
+    cpu.r[i.rd as usize] = cpu.r[i.rs1 as usize] | cpu.r[i.rs2 as usize];
+}
+
+fn op_xor(cpu: &mut CPU, i: &Inst) {
+    cpu.r[i.rd as usize] = cpu.r[i.rs1 as usize] ^ cpu.r[i.rs2 as usize];
+}
+
+fn op_not(cpu: &mut CPU, i: &Inst) {
+    cpu.r[i.rd as usize] = !cpu.r[i.rs1 as usize];
+}
+
+// Shift amounts use the low six bits of rs2.
+fn op_shl(cpu: &mut CPU, i: &Inst) {
+    cpu.r[i.rd as usize] = cpu.r[i.rs1 as usize] << (cpu.r[i.rs2 as usize] & 63);
+}
+
+fn op_shr(cpu: &mut CPU, i: &Inst) {
+    cpu.r[i.rd as usize] = cpu.r[i.rs1 as usize] >> (cpu.r[i.rs2 as usize] & 63);
+}
+
+fn op_sar(cpu: &mut CPU, i: &Inst) {
+    cpu.r[i.rd as usize] = ((cpu.r[i.rs1 as usize] as i64) >> (cpu.r[i.rs2 as usize] & 63)) as u64;
+}
+
 fn op_ld(cpu: &mut CPU, mem: &mut Memory, i: &Inst) {
     let cap = &cpu.c[2];
-    let addr = cpu.r[i.rs1 as usize].wrapping_add(i.imm as i64 as u64);
+    let addr = cpu.r[i.rs1 as usize].wrapping_add(i.imm as u64);
 
     match mem.load64(addr, cap) {
         Ok(v) => cpu.r[i.rd as usize] = v,
-        Err(t) => cpu.raise_trap(t),
+        Err(t) => cpu.fault(t, addr, 2),
     }
 }
 
+// st rs, imm(rs1). The value register travels in the rd field, where the
+// assembler has always put it: the low nibble holds the offset, and reading
+// the value from rs2 as well would store the offset's register.
 fn op_st(cpu: &mut CPU, mem: &mut Memory, i: &Inst) {
     let cap = &cpu.c[2];
-    let addr = cpu.r[i.rs1 as usize].wrapping_add(i.imm as i64 as u64);
-    let val  = cpu.r[i.rs2 as usize];
+    let addr = cpu.r[i.rs1 as usize].wrapping_add(i.imm as u64);
+    let val  = cpu.r[i.rd as usize];
 
     if let Err(t) = mem.store64(addr, val, cap) {
-        cpu.raise_trap(t);
+        cpu.fault(t, addr, 2);
+    }
+}
+
+// Effective address, authorising capability and that capability's register
+// for a sized access. Group MEM adds imm to the integer register rs1 and
+// checks against c[2]; group CMEM names a capability in rs1 and adds imm to
+// its current address.
+fn data_ref(cpu: &CPU, i: &Inst) -> Result<(u64, Capability, u8), Trap> {
+    if i.group == GROUP_CMEM {
+        let cap = creg(cpu, i.rs1)?;
+        Ok((cap.get_address().wrapping_add(i.imm as u64), cap, i.rs1))
+    } else {
+        Ok((cpu.r[i.rs1 as usize].wrapping_add(i.imm as u64), cpu.c[2], 2))
     }
 }
 
+// Sized load, sign- or zero-extended to 64 bits.
+fn op_load(cpu: &mut CPU, mem: &mut Memory, i: &Inst, size: u64, signed: bool) {
+    let (addr, cap, n) = match data_ref(cpu, i) {
+        Ok(r) => r,
+        Err(t) => {
+            cpu.raise_trap(t);
+            return;
+        }
+    };
+    let cap = &cap;
+
+    let v = match size {
+        1 => mem.load8(addr, cap).map(|v| if signed { v as i8 as u64 } else { v as u64 }),
+        2 => mem.load16(addr, cap).map(|v| if signed { v as i16 as u64 } else { v as u64 }),
+        4 => mem.load32(addr, cap).map(|v| if signed { v as i32 as u64 } else { v as u64 }),
+        _ => mem.load64(addr, cap),
+    };
+
+    match v {
+        Ok(v) => cpu.r[i.rd as usize] = v,
+        Err(t) => cpu.fault(t, addr, n),
+    }
+}
+
+// Sized store of the low bytes of the register in the rd field.
+fn op_store(cpu: &mut CPU, mem: &mut Memory, i: &Inst, size: u64) {
+    let (addr, cap, n) = match data_ref(cpu, i) {
+        Ok(r) => r,
+        Err(t) => {
+            cpu.raise_trap(t);
+            return;
+        }
+    };
+    let cap = &cap;
+    let val  = cpu.r[i.rd as usize];
+
+    let r = match size {
+        1 => mem.store8(addr, val as u8, cap),
+        2 => mem.store16(addr, val as u16, cap),
+        4 => mem.store32(addr, val as u32, cap),
+        _ => mem.store64(addr, val, cap),
+    };
+
+    if let Err(t) = r {
+        cpu.fault(t, addr, n);
+    }
+}
+
+// cap.load cd, imm(base): load a capability, keeping its tag.
+fn cap_load(cpu: &mut CPU, mem: &mut Memory, i: &Inst) {
+    let (addr, cap, n) = match data_ref(cpu, i) {
+        Ok(r) if i.rd < 8 => r,
+        Ok(_) => {
+            cpu.raise_trap(Trap::IllegalInstruction);
+            return;
+        }
+        Err(t) => {
+            cpu.raise_trap(t);
+            return;
+        }
+    };
+
+    match mem.load_cap(addr, &cap) {
+        Ok(c) => cpu.c[i.rd as usize] = c,
+        Err(t) => cpu.fault(t, addr, n),
+    }
+}
+
+// cap.store cs, imm(base): store a capability together with its tag.
+fn cap_store(cpu: &mut CPU, mem: &mut Memory, i: &Inst) {
+    let (addr, cap, n) = match data_ref(cpu, i) {
+        Ok(r) if i.rd < 8 => r,
+        Ok(_) => {
+            cpu.raise_trap(Trap::IllegalInstruction);
+            return;
+        }
+        Err(t) => {
+            cpu.raise_trap(t);
+            return;
+        }
+    };
+
+    if let Err(t) = mem.store_cap(addr, &cpu.c[i.rd as usize], &cap) {
+        cpu.fault(t, addr, n);
+    }
+}
+
+// br compares rs1 with the register in the rd field, again matching the
+// assembler; the low nibble is the branch offset.
 fn op_br(cpu: &mut CPU, i: &Inst) {
-    if cpu.r[i.rs1 as usize] == cpu.r[i.rs2 as usize] {
-        cpu.pc = cpu.pc.wrapping_add((i.imm as i64 * 2) as u64);
+    if cpu.r[i.rs1 as usize] == cpu.r[i.rd as usize] {
+        cpu.set_pc(cpu.pc().wrapping_add(i.imm.wrapping_mul(2) as u64));
     }
 }
 
 fn op_brz(cpu: &mut CPU, i: &Inst) {
     if cpu.r[i.rs1 as usize] == 0 {
-        cpu.pc = cpu.pc.wrapping_add((i.imm as i64 * 2) as u64);
+        cpu.set_pc(cpu.pc().wrapping_add(i.imm.wrapping_mul(2) as u64));
     }
 }
 
+// Conditional branches compare rs1 with the register in the rd field, like br.
+fn branch_if(cpu: &mut CPU, i: &Inst, taken: bool) {
+    if taken {
+        cpu.set_pc(cpu.pc().wrapping_add(i.imm.wrapping_mul(2) as u64));
+    }
+}
+
+fn op_bne(cpu: &mut CPU, i: &Inst) {
+    let taken = cpu.r[i.rs1 as usize] != cpu.r[i.rd as usize];
+    branch_if(cpu, i, taken);
+}
+
+fn op_blt(cpu: &mut CPU, i: &Inst) {
+    let taken = (cpu.r[i.rs1 as usize] as i64) < (cpu.r[i.rd as usize] as i64);
+    branch_if(cpu, i, taken);
+}
+
+fn op_bge(cpu: &mut CPU, i: &Inst) {
+    let taken = (cpu.r[i.rs1 as usize] as i64) >= (cpu.r[i.rd as usize] as i64);
+    branch_if(cpu, i, taken);
+}
+
+fn op_bltu(cpu: &mut CPU, i: &Inst) {
+    let taken = cpu.r[i.rs1 as usize] < cpu.r[i.rd as usize];
+    branch_if(cpu, i, taken);
+}
+
+fn op_bgeu(cpu: &mut CPU, i: &Inst) {
+    let taken = cpu.r[i.rs1 as usize] >= cpu.r[i.rd as usize];
+    branch_if(cpu, i, taken);
+}
 
 fn op_jmp(cpu: &mut CPU, i: &Inst) {
     if i.rs1 == 0 {
         // PC-relative jump (used for labels): pc += imm * 2
-        cpu.pc = cpu.pc.wrapping_add((i.imm as i64 * 2) as u64);
+        cpu.set_pc(cpu.pc().wrapping_add(i.imm.wrapping_mul(2) as u64));
     } else {
         // Register + offset jump
-        cpu.pc = cpu.r[i.rs1 as usize].wrapping_add(i.imm as i64 as u64);
+        cpu.set_pc(cpu.r[i.rs1 as usize].wrapping_add(i.imm as u64));
     }
 }
 
+// call leaves its return address as a capability in c[LINK_CAP]: the current
+// PCC with its cursor on the next instruction.
 fn op_call(cpu: &mut CPU, i: &Inst) {
-    cpu.r[15] = cpu.pc;
+    cpu.c[LINK_CAP] = cpu.pcc;
     if i.rs1 == 0 {
-        cpu.pc = cpu.pc.wrapping_add((i.imm as i64 * 2) as u64);
+        cpu.set_pc(cpu.pc().wrapping_add(i.imm.wrapping_mul(2) as u64));
     } else {
-        cpu.pc = cpu.r[i.rs1 as usize].wrapping_add(i.imm as i64 as u64);
-   ();
+        cpu.set_pc(cpu.r[i.rs1 as usize].wrapping_add(i.imm as u64));
     }
 }
 
+// ret: continue at the link capability, which becomes the PCC, as `jmp c6`
+// would.
 fn op_ret(cpu: &mut CPU, _i: &Inst) {
-    cpu.pc = cpu.r[15];
+    let link = cpu.c[LINK_CAP];
+    if is_code(&link) {
+        cpu.pcc = link;
+    } else {
+        cpu.fault(Trap::CapViolation, 0, LINK_CAP as u8);
+    }
 }
 
+// The stack is full-descending: the cursor of c[STACK_CAP] points at the most
+// recently pushed quadword, and every access is checked against that
+// capability. Both helpers raise the fault themselves.
+fn push64(cpu: &mut CPU, mem: &mut Memory, val: u64) -> bool {
+    let sp = cpu.c[STACK_CAP];
+    let offset = sp.offset.wrapping_sub(8);
+    let addr = sp.base.wrapping_add(offset);
+    match mem.store64(addr, val, &sp) {
+        Ok(()) => {
+            cpu.c[STACK_CAP].offset = offset;
+            true
+        }
+        Err(t) => {
+            cpu.fault(t, addr, STACK_CAP as u8);
+            false
+        }
+    }
+}
+
+fn pop64(cpu: &mut CPU, mem: &mut Memory) -> Option<u64> {
+    let sp = cpu.c[STACK_CAP];
+    let addr = sp.get_address();
+    match mem.load64(addr, &sp) {
+        Ok(v) => {
+            cpu.c[STACK_CAP].offset = sp.offset.wrapping_add(8);
+            Some(v)
+        }
+        Err(t) => {
+            cpu.fault(t, addr, STACK_CAP as u8);
+            None
+        }
+    }
+}
+
+// push rs (rs travels in the rd field)
+fn op_push(cpu: &mut CPU, mem: &mut Memory, i: &Inst) {
+    let val = cpu.r[i.rd as usize];
+    push64(cpu, mem, val);
+}
+
+fn op_pop(cpu: &mut CPU, mem: &mut Memory, i: &Inst) {
+    if let Some(v) = pop64(cpu, mem) {
+        cpu.r[i.rd as usize] = v;
+    }
+}
+
+// Like call, but the return address is pushed on the stack instead of being
+// left in the link capability, so nested and recursive calls need no manual
+// saving.
+fn op_pcall(cpu: &mut CPU, mem: &mut Memory, i: &Inst) {
+    if !push64(cpu, mem, cpu.pc()) {
+        return;
+    }
+    if i.rs1 == 0 {
+        cpu.set_pc(cpu.pc().wrapping_add(i.imm.wrapping_mul(2) as u64));
+    } else {
+        cpu.set_pc(cpu.r[i.rs1 as usize].wrapping_add(i.imm as u64));
+    }
+}
+
+fn op_pret(cpu: &mut CPU, mem: &mut Memory, _i: &Inst) {
+    if let Some(v) = pop64(cpu, mem) {
+        cpu.set_pc(v);
+    }
+}
+
+// The trap is set directly rather than through raise_trap: a supervisor
+// syscall is serviced without entering a handler, and a handler making one
+// must still find its own fault's badaddr and capability in the trap frame.
 fn op_syscall(cpu: &mut CPU, i: &Inst) {
     let n = cpu.r[i.rs1 as usize];
-    cpu.raise_trap(Trap::Syscall(n));
+    cpu.trap = Some(Trap::Syscall(n));
+}
+
+// Capability register named by a 4-bit field; only c0..c7 exist.
+fn creg(cpu: &CPU, n: u8) -> Result<Capability, Trap> {
+    cpu.c.get(n as usize).copied().ok_or(Trap::IllegalInstruction)
 }
 
 fn cap_null(cpu: &mut CPU, inst: &Inst) {
+    if inst.rd >= 8 {
+        cpu.raise_trap(Trap::IllegalInstruction);
+        return;
+    }
     cpu.c[inst.rd as usize] = Capability::null();
 }
 
 fn cap_copy(cpu: &mut CPU, inst: &Inst) {
-    cpu.c[inst.rd as usize] = cpu.c[inst.rs1 as usize];
+    match creg(cpu, inst.rs1) {
+        Ok(c) if inst.rd < 8 => cpu.c[inst.rd as usize] = c,
+        _ => cpu.raise_trap(Trap::IllegalInstruction),
+    }
 }
 
+// Moves the cursor. Leaving the bounds is allowed (accesses are checked), but
+// a cursor outside the representable region could not be stored compressed,
+// so the result loses its tag.
 fn cap_offset(cpu: &mut CPU, inst: &Inst) {
-    let src = cpu.c[inst.rs1 as usize];
+    let src = match creg(cpu, inst.rs1) {
+        Ok(c) if inst.rd < 8 => c,
+        _ => {
+            cpu.raise_trap(Trap::IllegalInstruction);
+            return;
+        }
+    };
+
+    if !src.valid || src.sealed {
+        cpu.fault(Trap::CapViolation, 0, inst.rs1);
+        return;
+    }
+
+    let new_offset = src.offset.wrapping_add(inst.imm as u64);
+
+    cpu.c[inst.rd as usize] = Capability {
+        offset: new_offset,
+        valid: cap_compressed::representable(&src, new_offset),
+        ..src
+    };
+}
+
+// Moves one field of cs into integer register rd. Reading metadata needs no
+// permission and works on untagged and sealed capabilities alike.
+fn cap_get(cpu: &mut CPU, inst: &Inst, field: fn(&Capability) -> u64) {
+    match creg(cpu, inst.rs1) {
+        Ok(c) => cpu.r[inst.rd as usize] = field(&c),
+        Err(t) => cpu.raise_trap(t),
+    }
+}
+
+// Derives from cs a capability starting at its current address and spanning
+// r[rs2] bytes, rounded out to the nearest bounds the compressed format can
+// encode. The result must still lie inside the old bounds.
+fn cap_setbounds(cpu: &mut CPU, inst: &Inst) {
+    let src = match creg(cpu, inst.rs1) {
+        Ok(c) if inst.rd < 8 => c,
+        _ => {
+            cpu.raise_trap(Trap::IllegalInstruction);
+            return;
+        }
+    };
 
     if !src.valid || src.sealed {
-        cpu.raise_trap(Trap::CapViolation);
+        cpu.fault(Trap::CapViolation, 0, inst.rs1);
         return;
     }
 
-    let new_offset = src.offset.wrapping_add(inst.imm as i64 as u64);
+    let length = cpu.r[inst.rs2 as usize];
 
-    if !src.in_bounds(new_offset, 0) {
-        cpu.raise_trap(Trap::OutOfBounds);
+    if !src.in_bounds(src.offset, length) {
+        cpu.fault(Trap::OutOfBounds, src.get_address(), inst.rs1);
+        return;
+    }
+
+    let addr = src.get_address();
+    let (base, length) = cap_compressed::round_bounds(addr, length);
+
+    if base < src.base || !src.in_bounds(base - src.base, length) {
+        cpu.fault(Trap::OutOfBounds, base, inst.rs1);
         return;
     }
 
     cpu.c[inst.rd as usize] = Capability {
-        offset: new_offset,
+        base,
+        length,
+        offset: addr - base,
+        ..src
+    };
+}
+
+// Clears every permission bit of cs that is not set in r[rs2].
+fn cap_andperm(cpu: &mut CPU, inst: &Inst) {
+    let src = match creg(cpu, inst.rs1) {
+        Ok(c) if inst.rd < 8 => c,
+        _ => {
+            cpu.raise_trap(Trap::IllegalInstruction);
+            return;
+        }
+    };
+
+    if !src.valid || src.sealed {
+        cpu.fault(Trap::CapViolation, 0, inst.rs1);
+        return;
+    }
+
+    cpu.c[inst.rd as usize] = Capability {
+        perms: src.perms & cpu.r[inst.rs2 as usize] as u8,
         ..src
     };
 }
+
+// Checks that `auth` may seal or unseal with the object type at its cursor
+// and returns that object type.
+fn sealing_otype(auth: &Capability, unseal: bool) -> Result<u64, Trap> {
+    if !auth.valid || auth.sealed {
+        return Err(Trap::CapViolation);
+    }
+    if (unseal && !auth.can_unseal()) || (!unseal && !auth.can_seal()) {
+        return Err(Trap::CapViolation);
+    }
+    if !auth.in_bounds(auth.offset, 1) {
+        return Err(Trap::OutOfBounds);
+    }
+    Ok(auth.get_address())
+}
+
+// cap.seal cd, cs, ct: seal cs with the object type selected by ct.
+fn cap_seal(cpu: &mut CPU, inst: &Inst) {
+    let (src, auth) = match (creg(cpu, inst.rs1), creg(cpu, inst.rs2)) {
+        (Ok(s), Ok(a)) if inst.rd < 8 => (s, a),
+        _ => {
+            cpu.raise_trap(Trap::IllegalInstruction);
+            return;
+        }
+    };
+
+    if !src.valid || src.sealed {
+        cpu.fault(Trap::CapViolation, 0, inst.rs1);
+        return;
+    }
+
+    match sealing_otype(&auth, false) {
+        Ok(otype) => {
+            cpu.c[inst.rd as usize] = Capability {
+                sealed: true,
+                otype,
+                ..src
+            };
+        }
+        Err(t) => cpu.fault(t, 0, inst.rs2),
+    }
+}
+
+// cap.unseal cd, cs, ct: unseal cs, which must carry ct's object type.
+fn cap_unseal(cpu: &mut CPU, inst: &Inst) {
+    let (src, auth) = match (creg(cpu, inst.rs1), creg(cpu, inst.rs2)) {
+        (Ok(s), Ok(a)) if inst.rd < 8 => (s, a),
+        _ => {
+            cpu.raise_trap(Trap::IllegalInstruction);
+            return;
+        }
+    };
+
+    if !src.valid || !src.sealed {
+        cpu.fault(Trap::CapViolation, 0, inst.rs1);
+        return;
+    }
+
+    match sealing_otype(&auth, true) {
+        Ok(otype) if otype == src.otype => {
+            cpu.c[inst.rd as usize] = Capability {
+                sealed: false,
+                otype: 0,
+                ..src
+            };
+        }
+        Ok(_) => cpu.fault(Trap::CapViolation, 0, inst.rs2),
+        Err(t) => cpu.fault(t, 0, inst.rs2),
+    }
+}
+
+// cap.invoke cc, cd: enter the compartment described by a sealed code/data
+// pair of the same object type. The caller's PCC and c[2] are pushed on the
+// CPU's call-frame stack; the unsealed code becomes the PCC and the unsealed
+// data replaces c[2]. Nesting deeper than MAX_FRAMES faults.
+fn cap_invoke(cpu: &mut CPU, inst: &Inst) {
+    let (code, data) = match (creg(cpu, inst.rd), creg(cpu, inst.rs1)) {
+        (Ok(c), Ok(d)) => (c, d),
+        _ => {
+            cpu.raise_trap(Trap::IllegalInstruction);
+            return;
+        }
+    };
+
+    if !code.valid || !data.valid || !code.sealed || !data.sealed
+        || code.otype != data.otype || !code.can_exec() || data.can_exec()
+    {
+        cpu.fault(Trap::CapViolation, 0, inst.rd);
+        return;
+    }
+    if cpu.frames.len() >= MAX_FRAMES {
+        cpu.fault(Trap::OutOfBounds, 0, inst.rd);
+        return;
+    }
+
+    cpu.frames.push(CallFrame {
+        pcc: cpu.pcc,
+        data: cpu.c[2],
+    });
+
+    cpu.pcc = Capability { sealed: false, otype: 0, ..code };
+    cpu.c[2] = Capability { sealed: false, otype: 0, ..data };
+}
+
+// cap.return: leave the current compartment, restoring the frame pushed by
+// the matching cap.invoke.
+fn cap_return(cpu: &mut CPU, _inst: &Inst) {
+    match cpu.frames.pop() {
+        Some(f) => {
+            cpu.pcc = f.pcc;
+            cpu.c[2] = f.data;
+        }
+        None => cpu.raise_trap(Trap::CapViolation),
+    }
+}
+
+// Whether `c` may become the PCC.
+fn is_code(c: &Capability) -> bool {
+    c.valid && !c.sealed && c.can_exec()
+}
+
+// Code capability that jmp/call cN would install as the PCC.
+fn jump_target(cpu: &CPU, inst: &Inst) -> Result<Capability, Trap> {
+    let target = creg(cpu, inst.rs1)?;
+    if !is_code(&target) {
+        return Err(Trap::CapViolation);
+    }
+    Ok(Capability {
+        offset: target.offset.wrapping_add(inst.imm as u64),
+        ..target
+    })
+}
+
+// jmp cN, imm: continue at cN's address + imm with cN as the new PCC, so the
+// target's own code bounds apply from the next fetch on. `jmp c6` returns
+// from `call cN`.
+fn cap_jmp(cpu: &mut CPU, inst: &Inst) {
+    match jump_target(cpu, inst) {
+        Ok(t) => cpu.pcc = t,
+        Err(t) => cpu.fault(t, 0, inst.rs1),
+    }
+}
+
+// call cN, imm: like jmp cN, leaving the return address as a capability in
+// c[LINK_CAP].
+fn cap_call(cpu: &mut CPU, inst: &Inst) {
+    match jump_target(cpu, inst) {
+        Ok(t) => {
+            cpu.c[LINK_CAP] = cpu.pcc;
+            cpu.pcc = t;
+        }
+        Err(t) => cpu.fault(t, 0, inst.rs1),
+    }
+}
+
+// eret: leave a trap handler, resuming at the EPCC. Handlers that want to skip
+// the faulting instruction move it forward with trap.setepc first.
+fn op_eret(cpu: &mut CPU, _inst: &Inst) {
+    if !cpu.tf.active {
+        cpu.raise_trap(Trap::IllegalInstruction);
+        return;
+    }
+    cpu.pcc = cpu.tf.epcc;
+    cpu.tf.active = false;
+}
+
+// trap.get rd, n: read trap frame field n (0 EPC address, 1 cause, 2 bad
+// address, 3 capability index, 4 instruction length).
+fn trap_get(cpu: &mut CPU, inst: &Inst) {
+    let tf = &cpu.tf;
+    let v = match inst.imm {
+        0 => tf.epcc.get_address(),
+        1 => tf.cause,
+        2 => tf.badaddr,
+        3 => tf.cap as u64,
+        4 => tf.len,
+        _ => {
+            cpu.raise_trap(Trap::IllegalInstruction);
+            return;
+        }
+    };
+    cpu.r[inst.rd as usize] = v;
+}
+
+// trap.setepc rs: move the EPCC's cursor to the address in rs (rs travels in
+// the rd field). Its bounds are unchanged, so eret cannot escape them.
+fn trap_setepc(cpu: &mut CPU, inst: &Inst) {
+    let epcc = &mut cpu.tf.epcc;
+    epcc.offset = cpu.r[inst.rd as usize].wrapping_sub(epcc.base);
+}
+
+// cap.settvec cs: install cs as the trap vector (cs travels in the rd field).
+// A null capability hands traps back to the host. A valid one must carry the
+// system permission, since handlers have to run in supervisor mode.
+fn cap_settvec(cpu: &mut CPU, inst: &Inst) {
+    match creg(cpu, inst.rd) {
+        Ok(c) if c.valid && !c.can_access_system() => cpu.fault(Trap::CapViolation, 0, inst.rd),
+        Ok(c) => cpu.tvec = c,
+        Err(t) => cpu.raise_trap(t),
+    }
+}
+
+fn cap_gettvec(cpu: &mut CPU, inst: &Inst) {
+    if inst.rd >= 8 {
+        cpu.raise_trap(Trap::IllegalInstruction);
+        return;
+    }
+    cpu.c[inst.rd as usize] = cpu.tvec;
+}
+
+fn cap_getroot(cpu: &mut CPU, inst: &Inst) {
+    if inst.rd >= 8 {
+        cpu.raise_trap(Trap::IllegalInstruction);
+        return;
+    }
+    cpu.c[inst.rd as usize] = cpu.root;
+}
+
+// timer.set rs: arm the timer to fire once the cycle counter reaches rs (rs
+// travels in the rd field). Writing it is also how a handler acknowledges the
+// interrupt.
+fn timer_set(cpu: &mut CPU, inst: &Inst) {
+    cpu.timecmp = cpu.r[inst.rd as usize];
+}
@@ -1,7 +1,51 @@
+// Base instructions are a single 16-bit word:
+//
+//     [15:12] opcode  [11:8] rd  [7:4] rs1  [3:0] rs2 / imm4
+//
+// Instructions with an immediate and a second source register (st, br and the
+// branch group) carry that register in the rd field, so the low nibble stays
+// free for imm4.
+//
+// Opcode 0xB doubles as the extension prefix. The word 0xB000 is `ret`; any
+// other 0xB word is a prefix of the form
+//
+//     [15:12] 0xB  [11:8] group  [7:6] imm size  [5:0] reserved (zero)
+//
+// followed by one instruction word in the base layout, whose opcode is looked
+// up in `group`, and then 0, 1, 2 or 4 little-endian words holding a 16, 32
+// or 64-bit sign-extended immediate that replaces imm4.
+
+pub const OP_EXT: u8 = 0xB;
+
+pub const GROUP_BASE: u8 = 0x0;
+pub const GROUP_IMM: u8 = 0x1;
+pub const GROUP_ALU: u8 = 0x2;
+pub const GROUP_BRANCH: u8 = 0x3;
+pub const GROUP_MEM: u8 = 0x4;
+pub const GROUP_CMEM: u8 = 0x5;
+pub const GROUP_CAP: u8 = 0x6;
+pub const GROUP_STACK: u8 = 0x7;
+pub const GROUP_TRAP: u8 = 0x8;
+pub const GROUP_COUNTER: u8 = 0x9;
+
 pub struct Inst {
+    pub group: u8,
     pub opcode: u8,
     pub rd: u8,
     pub rs1: u8,
     pub rs2: u8,
-    pub imm: i8,
+    pub imm: i64,
+    pub len: u64,
+}
+
+impl Inst {
+    /// The immediate zero-extended from the width it was encoded with.
+    pub fn uimm(&self) -> u64 {
+        let bits = if self.len <= 4 { 4 } else { (self.len - 4) * 8 };
+        if bits >= 64 {
+            self.imm as u64
+        } else {
+            self.imm as u64 & ((1u64 << bits) - 1)
+        }
+    }
 }
@@ -1,7 +1,14 @@
+pub mod bus;
 pub mod cap;
+pub mod cap_compressed;
 pub mod cpu;
 pub mod isa;
 pub mod mem;
 pub mod trap;
 pub mod exec;
-pub mod decode;
\ No newline at end of file
+pub mod decode;
+pub mod devices;
+pub mod loader;
+pub mod machine;
+pub mod revoke;
+pub mod syscall;
@@ -1,9 +1,18 @@
 use crate::{cpu::CPU, mem::Memory, cap::Capability};
+use crate::cap::OTYPE_COUNT;
+use crate::cpu::STACK_CAP;
 use std::fs;
 
+/// Size of the stack the loader carves out of the top of memory.
+pub const STACK_SIZE: u64 = 64 * 1024;
+
 pub fn load_osl_bin(cpu: &mut CPU, mem: &mut Memory, path: &str) -> Result<(), String> {
     let data = fs::read(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    load_osl_bytes(cpu, mem, &data)
+}
 
+/// Loads an in-memory .oslbin image and sets up the initial capabilities.
+pub fn load_osl_bytes(cpu: &mut CPU, mem: &mut Memory, data: &[u8]) -> Result<(), String> {
     if data.len() < 0x28 {
         return Err("binary too small".to_string());
     }
@@ -21,18 +30,24 @@ let text_size   = rd_u64(0x10);  // 0x10 = 16 → third field → text_size
 let data_base   = rd_u64(0x18);  // 0x18 = 24
 let data_size   = rd_u64(0x20);  // 0x20 = 32
 
-    if text_base.checked_add(text_size).map_or(true, |end| end as usize > mem.bytes.len()) {
+    if text_base.checked_add(text_size).is_none_or(|end| end as usize > mem.bytes.len()) {
         return Err(format!("text section out of bounds: base={:#x} size={:#x}", text_base, text_size));
     }
 
-    if data_base.checked_add(data_size).map_or(true, |end| end as usize > mem.bytes.len()) {
+    if data_base.checked_add(data_size).is_none_or(|end| end as usize > mem.bytes.len()) {
         return Err(format!("data section out of bounds: base={:#x} size={:#x}", data_base, data_size));
     }
 
+    let mem_size = mem.bytes.len() as u64;
+    let stack_base = mem_size.checked_sub(STACK_SIZE).ok_or("memory too small for stack")?;
+    if text_base + text_size > stack_base || data_base + data_size > stack_base {
+        return Err(format!("sections overlap the stack at {:#x}", stack_base));
+    }
+
     let text_start = 0x28;
     let data_start = text_start + text_size as usize;
 
-    if data_start.checked_add(data_size as usize).map_or(true, |end| end > data.len()) {
+    if data_start.checked_add(data_size as usize).is_none_or(|end| end > data.len()) {
         return Err("binary file truncated".to_string());
     }
 
@@ -44,24 +59,64 @@ let data_size   = rd_u64(0x20);  // 0x20 = 32
             .copy_from_slice(&data[data_start..data_start + data_size as usize]);
     }
 
-    cpu.pc = entry;
-
+    // Programs start in supervisor mode; a kernel clears the system
+    // permission (0x20) from the code capabilities it hands to user code.
     cpu.c[1] = Capability {
         base: text_base,
         length: text_size,
         offset: 0,
-        perms: 4,
+        perms: 0x24,
         valid: true,
         sealed: false,
+        otype: 0,
+    };
+    cpu.pcc = Capability {
+        offset: entry.wrapping_sub(text_base),
+        ..cpu.c[1]
     };
 
     cpu.c[2] = Capability {
         base: data_base,
         length: data_size,
         offset: 0,
-        perms: 3,
+        perms: 0x1B,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    };
+
+    // The stack grows down from the top of memory; the cursor is the stack
+    // pointer.
+    cpu.c[STACK_CAP] = Capability {
+        base: stack_base,
+        length: STACK_SIZE,
+        offset: STACK_SIZE,
+        perms: 0x1B,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    };
+
+    // Sealing root: its address range is the object-type space.
+    cpu.c[3] = Capability {
+        base: 0,
+        length: OTYPE_COUNT,
+        offset: 0,
+        perms: 0xC0,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    };
+
+    // The root spans the whole address space, devices included.
+    cpu.root = Capability {
+        base: 0,
+        length: u64::MAX,
+        offset: 0,
+        perms: 0xFF,
         valid: true,
         sealed: false,
+        otype: 0,
     };
 
     Ok(())
@@ -0,0 +1,132 @@
+// A complete emulated machine: CPU, RAM, the standard devices and the host
+// syscalls, driven by one loop. The emulator binary is a thin wrapper around
+// this; embedders can also reach into `cpu`, `mem` and `syscalls` directly.
+
+use crate::cpu::CPU;
+use crate::devices::block::{BlockDevice, Disk};
+use crate::devices::rng::Rng;
+use crate::devices::uart::{SerialPort, Uart};
+use crate::devices::{BLOCK_BASE, DEVICE_SIZE, RNG_BASE, UART_BASE};
+use crate::loader::load_osl_bytes;
+use crate::mem::Memory;
+use crate::syscall::{Env, Outcome, Syscalls};
+use crate::trap::Trap;
+
+pub struct Config {
+    /// Bytes of RAM, mapped from address 0.
+    pub mem_size: usize,
+    /// Line behind the UART. Without one the UART is not mapped.
+    pub serial: Option<Box<dyn SerialPort>>,
+    /// Disk behind the block device. Without one it is not mapped.
+    pub disk: Option<Box<dyn Disk>>,
+    /// Seed for the random number device.
+    pub rng_seed: u64,
+    /// Streams and directory the host syscalls use.
+    pub env: Env,
+}
+
+impl Default for Config {
+    /// 4 MiB of RAM, no UART or disk, syscalls on the host's stdio and
+    /// current directory.
+    fn default() -> Self {
+        Config {
+            mem_size: 4 * 1024 * 1024,
+            serial: None,
+            disk: None,
+            rng_seed: 0x2545_F491_4F6C_DD1D,
+            env: Env::new("."),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum StopReason {
+    /// The program called exit with this code.
+    Exited(u64),
+    /// A trap no guest handler took. `cpu.tf` describes it.
+    Trap(Trap),
+    /// `run_for` used up its steps.
+    StepLimit,
+}
+
+pub struct Machine {
+    pub cpu: CPU,
+    pub mem: Memory,
+    pub syscalls: Syscalls,
+    stopped: Option<StopReason>,
+}
+
+impl Machine {
+    pub fn new(config: Config) -> Result<Self, String> {
+        let mut mem = Memory::new(config.mem_size);
+        if let Some(line) = config.serial {
+            mem.map(UART_BASE, DEVICE_SIZE, Box::new(Uart::new(line)))?;
+        }
+        mem.map(RNG_BASE, DEVICE_SIZE, Box::new(Rng::new(config.rng_seed)))?;
+        if let Some(disk) = config.disk {
+            mem.map(BLOCK_BASE, DEVICE_SIZE, Box::new(BlockDevice::new(disk)))?;
+        }
+
+        Ok(Machine {
+            cpu: CPU::new(),
+            mem,
+            syscalls: Syscalls::with_defaults(config.env),
+            stopped: None,
+        })
+    }
+
+    /// Loads an .oslbin image in place of whatever ran before: the CPU, RAM
+    /// and syscall state (open files, heap) start fresh, while devices keep
+    /// their mappings. The CPU starts at the image's entry point.
+    pub fn load(&mut self, image: &[u8]) -> Result<(), String> {
+        self.cpu = CPU::new();
+        self.stopped = None;
+        self.mem.clear();
+        self.syscalls.env.reset();
+        load_osl_bytes(&mut self.cpu, &mut self.mem, image)?;
+        self.syscalls.env.attach(&self.cpu);
+        Ok(())
+    }
+
+    /// Executes one instruction and services any syscall it made. Once the
+    /// machine has stopped, keeps returning the same reason without running.
+    pub fn step(&mut self) -> Option<StopReason> {
+        if self.stopped.is_some() {
+            return self.stopped;
+        }
+
+        self.cpu.step(&mut self.mem);
+        self.stopped = match self.cpu.trap {
+            None => None,
+            Some(Trap::Syscall(n)) => {
+                self.cpu.trap = None;
+                match self.syscalls.dispatch(n, &mut self.cpu, &mut self.mem) {
+                    Outcome::Exit(code) => Some(StopReason::Exited(code)),
+                    Outcome::Return(_) => None,
+                }
+            }
+            Some(t) => Some(StopReason::Trap(t)),
+        };
+        self.stopped
+    }
+
+    /// Runs until the program exits or traps.
+    pub fn run(&mut self) -> StopReason {
+        loop {
+            if let Some(r) = self.step() {
+                return r;
+            }
+        }
+    }
+
+    /// Runs at most `n` instructions. Hitting the limit does not stop the
+    /// machine; calling again continues where it left off.
+    pub fn run_for(&mut self, n: u64) -> StopReason {
+        for _ in 0..n {
+            if let Some(r) = self.step() {
+                return r;
+            }
+        }
+        StopReason::StepLimit
+    }
+}
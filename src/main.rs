@@ -1,82 +1,43 @@
 // src/main.rs – EMULATOR (the program that runs .oslbin files)
 
-mod cpu;
-mod cap;
-mod mem;
-mod isa;
-mod decode;
-mod exec;
-mod loader;
-mod trap;
-
-use cpu::CPU;
-use mem::Memory;
-use loader::load_osl_bin;
-use trap::{Trap, trap_name};
+use hephaestus_isa::devices::block::FileDisk;
+use hephaestus_isa::devices::uart::HostSerial;
+use hephaestus_isa::machine::{Config, Machine, StopReason};
+use hephaestus_isa::trap::trap_name;
 use std::env;
-
-fn handle_syscall(cpu: &mut CPU, mem: &mut Memory, n: u64) {
-    match n {
-        0 => {
-            println!("Program exited with code {}", cpu.r[1]);
-            cpu.trap = Some(Trap::IllegalInstruction); // This tells the loop to stop
-        }
-        1 => {
-            println!("{}", cpu.r[1]);
-        }
-        2 => {
-            let addr = cpu.r[1];
-            let cap = &cpu.c[2];
-            let mut p = addr;
-            loop {
-                let b = match mem.load8(p, cap) {
-                    Ok(v) => v,
-                    Err(t) => {
-                        cpu.raise_trap(t);
-                        return;
-                    }
-                };
-                if b == 0 { break; }
-                print!("{}", b as char);
-                p += 1;
-            }
-            println!();
-        }
-        _ => println!("Unknown syscall {}", n),
-    }
-}
+use std::fs;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
-        eprintln!("Usage: {} <program.oslbin>", args[0]);
+        eprintln!("Usage: {} <program.oslbin> [disk.img]", args[0]);
         return;
     }
 
-    let mut cpu = CPU::new();
-    let mut mem = Memory::new(4 * 1024 * 1024);
-    load_osl_bin(&mut cpu, &mut mem, &args[1]).expect("failed to load");
+    // The UART and fd 0 share one stdin reader, so neither steals the
+    // other's input.
+    let serial = HostSerial::new();
+    let mut config = Config::default();
+    config.env.stdin = Box::new(serial.stdin());
+    config.serial = Some(Box::new(serial));
+    if let Some(path) = args.get(2) {
+        config.disk = Some(Box::new(FileDisk::open(path).expect("failed to open disk image")));
+    }
+    let mut machine = Machine::new(config).expect("failed to map devices");
+
+    let image = fs::read(&args[1]).expect("failed to read program");
+    machine.load(&image).expect("failed to load");
 
     println!("Loaded program, starting execution...\n");
 
-    loop {
-        // Handle any trap (syscall or error)
-        if let Some(trap) = cpu.trap.take() {
-            match trap {
-                Trap::Syscall(n) => handle_syscall(&mut cpu, &mut mem, n),
-                _ => {
-                    println!("Trap: {}", trap_name(trap));
-                    break;
-                }
-            }
+    match machine.run() {
+        StopReason::Exited(code) => {
+            println!("Program exited with code {}", code);
+            std::process::exit(code as i32);
         }
-
-        // If exit syscall set a trap, we break out of the loop
-        if cpu.trap.is_some() {
-            break;
+        StopReason::Trap(trap) => {
+            println!("Trap: {} at pc {:#x}", trap_name(trap), machine.cpu.tf.epcc.get_address());
         }
-
-        // Run one instruction
-        cpu.step(&mut mem);
+        StopReason::StepLimit => unreachable!("run has no step limit"),
     }
-}
\ No newline at end of file
+}
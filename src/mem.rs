@@ -1,38 +1,134 @@
-use crate::cap::Capability;
+use crate::bus::{Bus, Device};
+use crate::cap::{Capability, CAP_SIZE};
+use crate::cap_compressed::{compress, decompress};
 use crate::trap::Trap;
 
+struct Mapping {
+    base: u64,
+    size: u64,
+    dev: Box<dyn Device>,
+}
+
+/// RAM from address zero plus any devices mapped above it. Every access is
+/// checked against its capability first and then routed through `Bus`.
 pub struct Memory {
     pub bytes: Vec<u8>,
+    /// One tag per CAP_SIZE granule, set only while the granule holds a
+    /// valid capability written by `store_cap`.
+    pub tags: Vec<bool>,
+    devices: Vec<Mapping>,
 }
 
 impl Memory {
     pub fn new(size: usize) -> Self {
-        Memory { bytes: vec![0; size] }
+        Memory {
+            bytes: vec![0; size],
+            tags: vec![false; size.div_ceil(CAP_SIZE as usize)],
+            devices: Vec::new(),
+        }
     }
 
-    pub fn load8(&self, addr: u64, cap: &Capability) -> Result<u8, Trap> {
+    /// Zeroes RAM and clears every tag. Device mappings stay as they are.
+    pub fn clear(&mut self) {
+        self.bytes.fill(0);
+        self.tags.fill(false);
+    }
+
+    pub fn load8(&mut self, addr: u64, cap: &Capability) -> Result<u8, Trap> {
         self.check_read(addr, 1, cap)?;
-        Ok(self.bytes[addr as usize])
+        self.read(addr, 1).map(|v| v as u8)
+    }
+
+    pub fn load16(&mut self, addr: u64, cap: &Capability) -> Result<u16, Trap> {
+        self.check_read(addr, 2, cap)?;
+        self.read(addr, 2).map(|v| v as u16)
+    }
+
+    pub fn load32(&mut self, addr: u64, cap: &Capability) -> Result<u32, Trap> {
+        self.check_read(addr, 4, cap)?;
+        self.read(addr, 4).map(|v| v as u32)
     }
 
-    pub fn load64(&self, addr: u64, cap: &Capability) -> Result<u64, Trap> {
+    pub fn load64(&mut self, addr: u64, cap: &Capability) -> Result<u64, Trap> {
         self.check_read(addr, 8, cap)?;
+        self.read(addr, 8)
+    }
+
+    pub fn store8(&mut self, addr: u64, val: u8, cap: &Capability) -> Result<(), Trap> {
+        self.check_write(addr, 1, cap)?;
+        self.write(addr, 1, val as u64)
+    }
 
-        let mut buf = [0u8; 8];
-        buf.copy_from_slice(&self.bytes[addr as usize..addr as usize + 8]);
-        Ok(u64::from_le_bytes(buf))
+    pub fn store16(&mut self, addr: u64, val: u16, cap: &Capability) -> Result<(), Trap> {
+        self.check_write(addr, 2, cap)?;
+        self.write(addr, 2, val as u64)
+    }
+
+    pub fn store32(&mut self, addr: u64, val: u32, cap: &Capability) -> Result<(), Trap> {
+        self.check_write(addr, 4, cap)?;
+        self.write(addr, 4, val as u64)
     }
 
     pub fn store64(&mut self, addr: u64, val: u64, cap: &Capability) -> Result<(), Trap> {
         self.check_write(addr, 8, cap)?;
+        self.write(addr, 8, val)
+    }
+
+    // Capabilities only live in RAM, which is the only memory with tags.
+    // Loading needs the authorising capability's load-capability permission
+    // and storing its store-capability permission, tagged data or not.
+    pub fn load_cap(&self, addr: u64, cap: &Capability) -> Result<Capability, Trap> {
+        self.check_read(addr, CAP_SIZE, cap)?;
+        if !addr.is_multiple_of(CAP_SIZE) {
+            return Err(Trap::Misaligned);
+        }
+        self.check_ram(addr, CAP_SIZE)?;
+        if !cap.can_load_cap() {
+            return Err(Trap::CapViolation);
+        }
+
+        let a = addr as usize;
+        Ok(decompress(&self.bytes[a..a + CAP_SIZE as usize], self.tags[a / CAP_SIZE as usize]))
+    }
+
+    pub fn store_cap(&mut self, addr: u64, val: &Capability, cap: &Capability) -> Result<(), Trap> {
+        self.check_write(addr, CAP_SIZE, cap)?;
+        if !addr.is_multiple_of(CAP_SIZE) {
+            return Err(Trap::Misaligned);
+        }
+        self.check_ram(addr, CAP_SIZE)?;
+        if !cap.can_store_cap() {
+            return Err(Trap::CapViolation);
+        }
 
-        let bytes = val.to_le_bytes();
-        self.bytes[addr as usize..addr as usize + 8].copy_from_slice(&bytes);
+        // A capability the compressed format cannot hold exactly is stored
+        // untagged rather than with widened bounds.
+        let (bytes, exact) = compress(val);
+        let a = addr as usize;
+        self.bytes[a..a + CAP_SIZE as usize].copy_from_slice(&bytes);
+        self.tags[a / CAP_SIZE as usize] = val.valid && exact;
         Ok(())
     }
 
+    /// Invalidates any capability overlapping [addr, addr + size). Every
+    /// non-capability write goes through this so capabilities cannot be
+    /// forged byte by byte.
+    pub fn clear_tags(&mut self, addr: u64, size: u64) {
+        if size == 0 {
+            return;
+        }
+        let first = (addr / CAP_SIZE) as usize;
+        let last = ((addr + size - 1) / CAP_SIZE) as usize;
+        for t in &mut self.tags[first..=last] {
+            *t = false;
+        }
+    }
+
+    /// Fetches an instruction word. Code must be in RAM; devices cannot be
+    /// executed from.
     pub fn fetch16(&self, pc: u64, cap: &Capability) -> Result<u16, Trap> {
         self.check_exec(pc, 2, cap)?;
+        self.check_ram(pc, 2)?;
 
         let mut buf = [0u8; 2];
         buf.copy_from_slice(&self.bytes[pc as usize..pc as usize + 2]);
@@ -76,11 +172,78 @@ impl Memory {
             return Err(Trap::OutOfBounds);
         }
 
+        Ok(())
+    }
+
+    fn check_ram(&self, addr: u64, size: u64) -> Result<(), Trap> {
         let end = addr.checked_add(size).ok_or(Trap::OutOfBounds)?;
         if end as usize > self.bytes.len() {
             return Err(Trap::OutOfBounds);
         }
+        Ok(())
+    }
 
+    /// Maps `dev` at [base, base + size). The range must not overlap RAM or
+    /// another device.
+    pub fn map(&mut self, base: u64, size: u64, dev: Box<dyn Device>) -> Result<(), String> {
+        let end = base.checked_add(size)
+            .ok_or_else(|| format!("device range overflows at {:#x}", base))?;
+        if base < self.bytes.len() as u64 {
+            return Err(format!("device at {:#x} overlaps RAM", base));
+        }
+        if self.devices.iter().any(|m| base < m.base + m.size && m.base < end) {
+            return Err(format!("device at {:#x} overlaps another device", base));
+        }
+        self.devices.push(Mapping { base, size, dev });
         Ok(())
     }
+
+    // Index of the device whose range holds all of [addr, addr + size), and
+    // the offset into it.
+    fn device(&self, addr: u64, size: u64) -> Option<(usize, u64)> {
+        let k = self.devices.iter()
+            .position(|m| addr >= m.base && addr - m.base < m.size)?;
+        let off = addr - self.devices[k].base;
+        if off + size > self.devices[k].size {
+            return None;
+        }
+        Some((k, off))
+    }
+}
+
+impl Bus for Memory {
+    fn read(&mut self, addr: u64, size: u64) -> Result<u64, Trap> {
+        if self.check_ram(addr, size).is_ok() {
+            let a = addr as usize;
+            let mut buf = [0u8; 8];
+            buf[..size as usize].copy_from_slice(&self.bytes[a..a + size as usize]);
+            return Ok(u64::from_le_bytes(buf));
+        }
+        match self.device(addr, size) {
+            Some((k, off)) => Ok(self.devices[k].dev.read(off, size)),
+            None => Err(Trap::OutOfBounds),
+        }
+    }
+
+    fn write(&mut self, addr: u64, size: u64, val: u64) -> Result<(), Trap> {
+        if self.check_ram(addr, size).is_ok() {
+            let a = addr as usize;
+            self.bytes[a..a + size as usize].copy_from_slice(&val.to_le_bytes()[..size as usize]);
+            self.clear_tags(addr, size);
+            return Ok(());
+        }
+        match self.device(addr, size) {
+            Some((k, off)) => {
+                self.devices[k].dev.write(off, size, val);
+
+                // Devices are detached while one runs its DMA, so DMA only
+                // ever reaches RAM.
+                let mut devices = std::mem::take(&mut self.devices);
+                devices[k].dev.dma(self);
+                self.devices = devices;
+                Ok(())
+            }
+            None => Err(Trap::OutOfBounds),
+        }
+    }
 }
@@ -0,0 +1,86 @@
+// Capability revocation, after Cornucopia.
+//
+// Freed memory is painted in a shadow bitmap with one bit per 16-byte
+// granule. A sweep then visits every capability the machine can reach, in
+// registers and in tagged memory, and clears the tag of any whose base lies
+// in painted memory. Capabilities derived from an allocation keep its base
+// or a higher one, so after a sweep no copy of a freed object's capability
+// survives and the memory can safely be handed out again.
+
+use crate::cap::{CAP_SIZE, Capability};
+use crate::cap_compressed::decompress;
+use crate::cpu::CPU;
+use crate::mem::Memory;
+
+pub struct Revoker {
+    shadow: Vec<u64>,
+}
+
+impl Revoker {
+    /// A revoker for `mem_size` bytes of RAM with nothing painted.
+    pub fn new(mem_size: usize) -> Self {
+        let granules = mem_size.div_ceil(CAP_SIZE as usize);
+        Revoker { shadow: vec![0; granules.div_ceil(64)] }
+    }
+
+    /// Marks [base, base + len) as freed. Partial granules are painted whole.
+    pub fn paint(&mut self, base: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        let first = base / CAP_SIZE;
+        let last = (base + len - 1) / CAP_SIZE;
+        for g in first..=last.min(self.granules() - 1) {
+            self.shadow[(g / 64) as usize] |= 1 << (g % 64);
+        }
+    }
+
+    /// Forgets everything painted, once a sweep has finished with it.
+    pub fn clear(&mut self) {
+        self.shadow.fill(0);
+    }
+
+    /// Whether `c` is a live capability into painted memory.
+    pub fn revoked(&self, c: &Capability) -> bool {
+        let g = c.base / CAP_SIZE;
+        c.valid && g < self.granules() && self.shadow[(g / 64) as usize] & (1 << (g % 64)) != 0
+    }
+
+    /// Untags every capability into painted memory, returning how many.
+    pub fn sweep(&self, cpu: &mut CPU, mem: &mut Memory) -> u64 {
+        let mut n = 0;
+        let mut check = |c: &mut Capability| {
+            if self.revoked(c) {
+                c.valid = false;
+                n += 1;
+            }
+        };
+
+        cpu.c.iter_mut().for_each(&mut check);
+        check(&mut cpu.pcc);
+        check(&mut cpu.tvec);
+        check(&mut cpu.tf.epcc);
+        check(&mut cpu.root);
+        for f in &mut cpu.frames {
+            check(&mut f.pcc);
+            check(&mut f.data);
+        }
+
+        for k in 0..mem.tags.len() {
+            if !mem.tags[k] {
+                continue;
+            }
+            let a = k * CAP_SIZE as usize;
+            let c = decompress(&mem.bytes[a..a + CAP_SIZE as usize], true);
+            if self.revoked(&c) {
+                mem.tags[k] = false;
+                n += 1;
+            }
+        }
+        n
+    }
+
+    fn granules(&self) -> u64 {
+        self.shadow.len() as u64 * 64
+    }
+}
@@ -0,0 +1,587 @@
+// Host calls made with `syscall rN`.
+//
+// Register convention:
+//
+//     rN          syscall number (the register named by the instruction)
+//     r1 .. r5    arguments
+//     r1          return value, u64::MAX on failure
+//     r2          errno: 0 on success, one of the E* constants otherwise
+//     c5          capability result, for calls that hand out memory
+//
+// Buffers are passed as capability register numbers: `write(1, 3, 16)`
+// writes the 16 bytes starting at c3's address. The host checks the whole
+// range against the capability's tag, seal, bounds and permissions before
+// copying anything, and fails with EFAULT otherwise. Only the legacy
+// print_str call still takes a bare address, checked against c[2].
+// Unknown numbers fail with ENOSYS rather than stopping the machine.
+
+use crate::cap::{CAP_SIZE, Capability};
+use crate::cap_compressed::{alignment, round_bounds};
+use crate::cpu::{CPU, STACK_CAP};
+use crate::mem::Memory;
+use crate::revoke::Revoker;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SYS_EXIT: u64 = 0;
+/// Prints r1 in decimal on its own line.
+pub const SYS_PRINT_INT: u64 = 1;
+/// Prints the NUL-terminated string at address r1, within c[2], on its own line.
+pub const SYS_PRINT_STR: u64 = 2;
+pub const SYS_WRITE: u64 = 3;
+pub const SYS_READ: u64 = 4;
+pub const SYS_OPEN: u64 = 5;
+pub const SYS_CLOSE: u64 = 6;
+pub const SYS_BRK: u64 = 7;
+pub const SYS_MMAP: u64 = 8;
+pub const SYS_TIME: u64 = 9;
+pub const SYS_MALLOC: u64 = 10;
+pub const SYS_FREE: u64 = 11;
+pub const SYS_REVOKE: u64 = 12;
+
+/// Capability register that receives capability results.
+pub const SYS_CAP: usize = 5;
+
+// `open` flags, as on Linux.
+pub const O_RDONLY: u64 = 0;
+pub const O_WRONLY: u64 = 1;
+pub const O_RDWR: u64 = 2;
+pub const O_CREAT: u64 = 0x40;
+pub const O_TRUNC: u64 = 0x200;
+pub const O_APPEND: u64 = 0x400;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Errno(pub u64);
+
+pub const ENOENT: Errno = Errno(2);
+pub const EIO: Errno = Errno(5);
+pub const EBADF: Errno = Errno(9);
+pub const ENOMEM: Errno = Errno(12);
+pub const EACCES: Errno = Errno(13);
+pub const EFAULT: Errno = Errno(14);
+pub const EINVAL: Errno = Errno(22);
+pub const EMFILE: Errno = Errno(24);
+pub const ENOSYS: Errno = Errno(38);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Resume the guest; the value has been placed in r1.
+    Return(u64),
+    /// The guest asked to stop with this exit code.
+    Exit(u64),
+}
+
+pub type SysResult = Result<Outcome, Errno>;
+
+/// One host call. Closures with the same signature implement it, so embedders
+/// can register ad-hoc calls.
+pub trait Syscall {
+    fn call(&mut self, env: &mut Env, cpu: &mut CPU, mem: &mut Memory) -> SysResult;
+}
+
+impl<F> Syscall for F
+where
+    F: FnMut(&mut Env, &mut CPU, &mut Memory) -> SysResult,
+{
+    fn call(&mut self, env: &mut Env, cpu: &mut CPU, mem: &mut Memory) -> SysResult {
+        self(env, cpu, mem)
+    }
+}
+
+/// Host state the default calls share.
+pub struct Env {
+    pub stdin: Box<dyn Read>,
+    pub stdout: Box<dyn Write>,
+    pub stderr: Box<dyn Write>,
+    /// Directory guest paths are resolved in. Absolute paths and `..` are
+    /// refused, so the guest cannot name anything outside it.
+    pub root: PathBuf,
+    /// Open files; fd 3 is `files[0]`.
+    files: Vec<Option<File>>,
+    // Program break and lowest mmap address, set up by `attach`.
+    brk: Option<(u64, u64)>,
+    // The data capability the loader issued, widened as the break grows.
+    data: Capability,
+    /// Live malloc blocks, base -> (capability length, block size).
+    allocs: BTreeMap<u64, (u64, u64)>,
+    /// Freed malloc blocks waiting for reuse, as (base, size).
+    spare: Vec<(u64, u64)>,
+    /// Freed malloc blocks that may still be referenced, and their total size.
+    quarantine: Vec<(u64, u64)>,
+    quarantined: u64,
+    revoker: Option<Revoker>,
+    /// Bytes free() may quarantine before it sweeps. Larger batches make
+    /// frees cheaper on average but leave dangling capabilities usable (on
+    /// zeroed memory that is not reallocated) until the sweep.
+    pub revoke_batch: u64,
+}
+
+impl Env {
+    /// The host's standard streams, with paths resolved in `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Env {
+            stdin: Box::new(std::io::stdin()),
+            stdout: Box::new(std::io::stdout()),
+            stderr: Box::new(std::io::stderr()),
+            root: root.into(),
+            files: Vec::new(),
+            brk: None,
+            data: Capability::null(),
+            allocs: BTreeMap::new(),
+            spare: Vec::new(),
+            quarantine: Vec::new(),
+            quarantined: 0,
+            revoker: None,
+            revoke_batch: 0,
+        }
+    }
+
+    /// Closes every file and forgets the heap, so the next program starts
+    /// as if this Env were new. The streams, root and batch size are kept.
+    pub fn reset(&mut self) {
+        self.files.clear();
+        self.brk = None;
+        self.data = Capability::null();
+        self.allocs.clear();
+        self.spare.clear();
+        self.quarantine.clear();
+        self.quarantined = 0;
+        self.revoker = None;
+    }
+
+    /// Takes the data capability and stack the loader left in c[2] and
+    /// c[STACK_CAP] as the bounds of the heap, which lies between them.
+    /// `Machine` does this on every load; otherwise the first heap call does
+    /// it with the registers as they are then.
+    pub fn attach(&mut self, cpu: &CPU) {
+        let data = cpu.c[2];
+        self.data = data;
+        self.brk = Some((data.base + data.length, cpu.c[STACK_CAP].base));
+    }
+
+    fn file(&mut self, fd: u64) -> Result<&mut File, Errno> {
+        let k = fd.checked_sub(3).ok_or(EBADF)? as usize;
+        self.files.get_mut(k).and_then(|f| f.as_mut()).ok_or(EBADF)
+    }
+}
+
+pub struct Syscalls {
+    pub env: Env,
+    table: HashMap<u64, Box<dyn Syscall>>,
+}
+
+impl Syscalls {
+    /// A registry with no calls.
+    pub fn new(env: Env) -> Self {
+        Syscalls { env, table: HashMap::new() }
+    }
+
+    /// A registry with the SYS_* calls above.
+    pub fn with_defaults(env: Env) -> Self {
+        let mut s = Self::new(env);
+        s.register(SYS_EXIT, Box::new(sys_exit));
+        s.register(SYS_PRINT_INT, Box::new(sys_print_int));
+        s.register(SYS_PRINT_STR, Box::new(sys_print_str));
+        s.register(SYS_WRITE, Box::new(sys_write));
+        s.register(SYS_READ, Box::new(sys_read));
+        s.register(SYS_OPEN, Box::new(sys_open));
+        s.register(SYS_CLOSE, Box::new(sys_close));
+        s.register(SYS_BRK, Box::new(sys_brk));
+        s.register(SYS_MMAP, Box::new(sys_mmap));
+        s.register(SYS_TIME, Box::new(sys_time));
+        s.register(SYS_MALLOC, Box::new(sys_malloc));
+        s.register(SYS_FREE, Box::new(sys_free));
+        s.register(SYS_REVOKE, Box::new(sys_revoke));
+        s
+    }
+
+    /// Installs `call` as syscall `n`, replacing any previous one.
+    pub fn register(&mut self, n: u64, call: Box<dyn Syscall>) {
+        self.table.insert(n, call);
+    }
+
+    /// Runs syscall `n` and writes its result to r1/r2.
+    pub fn dispatch(&mut self, n: u64, cpu: &mut CPU, mem: &mut Memory) -> Outcome {
+        let r = match self.table.get_mut(&n) {
+            Some(call) => call.call(&mut self.env, cpu, mem),
+            None => Err(ENOSYS),
+        };
+        match r {
+            Ok(Outcome::Return(v)) => {
+                cpu.r[1] = v;
+                cpu.r[2] = 0;
+                Outcome::Return(v)
+            }
+            Ok(exit) => exit,
+            Err(e) => {
+                cpu.r[1] = u64::MAX;
+                cpu.r[2] = e.0;
+                Outcome::Return(u64::MAX)
+            }
+        }
+    }
+}
+
+/// The capability register named by a syscall argument.
+pub fn cap_arg(cpu: &CPU, reg: u64) -> Result<Capability, Errno> {
+    usize::try_from(reg).ok().and_then(|k| cpu.c.get(k)).copied().ok_or(EINVAL)
+}
+
+// Checks `len` bytes from the capability's address up front, so a call
+// either transfers everything or touches nothing.
+fn check_buffer(cap: &Capability, len: u64, write: bool) -> Result<(), Errno> {
+    let perm = if write { cap.can_write() } else { cap.can_read() };
+    if !cap.valid || cap.sealed || !perm || !cap.in_bounds(cap.offset, len) {
+        return Err(EFAULT);
+    }
+    Ok(())
+}
+
+/// Copies `len` guest bytes out through `cap`, starting at its address.
+pub fn read_guest(mem: &mut Memory, cap: &Capability, len: u64) -> Result<Vec<u8>, Errno> {
+    check_buffer(cap, len, false)?;
+    let addr = cap.get_address();
+    (0..len).map(|k| mem.load8(addr + k, cap).map_err(|_| EFAULT)).collect()
+}
+
+/// Copies `bytes` into the guest through `cap`, starting at its address.
+pub fn write_guest(mem: &mut Memory, cap: &Capability, bytes: &[u8]) -> Result<(), Errno> {
+    check_buffer(cap, bytes.len() as u64, true)?;
+    let addr = cap.get_address();
+    for (k, b) in bytes.iter().enumerate() {
+        mem.store8(addr + k as u64, *b, cap).map_err(|_| EFAULT)?;
+    }
+    Ok(())
+}
+
+/// Reads a NUL-terminated string through `cap`, up to `max` bytes. The
+/// terminator must lie inside the capability.
+pub fn read_guest_str(mem: &mut Memory, cap: &Capability, max: u64) -> Result<Vec<u8>, Errno> {
+    check_buffer(cap, 0, false)?;
+    let addr = cap.get_address();
+    let mut out = Vec::new();
+    for k in 0..max {
+        if !cap.in_bounds(cap.offset, k + 1) {
+            return Err(EFAULT);
+        }
+        match mem.load8(addr + k, cap).map_err(|_| EFAULT)? {
+            0 => return Ok(out),
+            b => out.push(b),
+        }
+    }
+    Err(EINVAL)
+}
+
+fn sys_exit(_: &mut Env, cpu: &mut CPU, _: &mut Memory) -> SysResult {
+    Ok(Outcome::Exit(cpu.r[1]))
+}
+
+fn sys_print_int(env: &mut Env, cpu: &mut CPU, _: &mut Memory) -> SysResult {
+    writeln!(env.stdout, "{}", cpu.r[1]).map_err(|_| EIO)?;
+    Ok(Outcome::Return(0))
+}
+
+fn sys_print_str(env: &mut Env, cpu: &mut CPU, mem: &mut Memory) -> SysResult {
+    let data = cpu.c[2];
+    let at = Capability { offset: cpu.r[1].wrapping_sub(data.base), ..data };
+    let s = read_guest_str(mem, &at, 1 << 20)?;
+    env.stdout.write_all(&s).and_then(|_| writeln!(env.stdout)).map_err(|_| EIO)?;
+    Ok(Outcome::Return(0))
+}
+
+// write(fd, c[buf], len) -> bytes written
+fn sys_write(env: &mut Env, cpu: &mut CPU, mem: &mut Memory) -> SysResult {
+    let buf = read_guest(mem, &cap_arg(cpu, cpu.r[2])?, cpu.r[3])?;
+    let r = match cpu.r[1] {
+        1 => env.stdout.write_all(&buf).and_then(|_| env.stdout.flush()),
+        2 => env.stderr.write_all(&buf),
+        fd => env.file(fd)?.write_all(&buf),
+    };
+    r.map_err(|_| EIO)?;
+    Ok(Outcome::Return(buf.len() as u64))
+}
+
+// read(fd, c[buf], len) -> bytes read, 0 at end of file
+fn sys_read(env: &mut Env, cpu: &mut CPU, mem: &mut Memory) -> SysResult {
+    let dst = cap_arg(cpu, cpu.r[2])?;
+    check_buffer(&dst, cpu.r[3], true)?;
+    let mut buf = vec![0u8; cpu.r[3].min(1 << 20) as usize];
+    let n = match cpu.r[1] {
+        0 => env.stdin.read(&mut buf),
+        fd => env.file(fd)?.read(&mut buf),
+    }
+    .map_err(|_| EIO)?;
+    write_guest(mem, &dst, &buf[..n])?;
+    Ok(Outcome::Return(n as u64))
+}
+
+// Resolves a guest path inside `root`. Symlinks are followed, but whatever
+// they lead to must still be under the root. The check and the later open are
+// separate steps, so this is racy against symlinks changed on the host in
+// between; it keeps the guest in, not a concurrent host process.
+fn sandboxed(root: &Path, path: &[u8]) -> Result<PathBuf, Errno> {
+    let path = std::str::from_utf8(path).map_err(|_| EINVAL)?;
+    let mut out = root.to_path_buf();
+    for c in Path::new(path).components() {
+        match c {
+            Component::Normal(p) => out.push(p),
+            Component::CurDir => {}
+            _ => return Err(EACCES),
+        }
+    }
+
+    let root = root.canonicalize().map_err(|_| ENOENT)?;
+    let real = match out.canonicalize() {
+        Ok(p) => p,
+        // A dangling symlink could still be created through.
+        Err(_) if out.symlink_metadata().is_ok() => return Err(EACCES),
+        // A file about to be created: check the directory it goes in.
+        Err(_) => out.parent().ok_or(EACCES)?.canonicalize().map_err(|_| ENOENT)?,
+    };
+    if !real.starts_with(&root) {
+        return Err(EACCES);
+    }
+    Ok(out)
+}
+
+// open(c[path], flags) -> fd
+fn sys_open(env: &mut Env, cpu: &mut CPU, mem: &mut Memory) -> SysResult {
+    let path = read_guest_str(mem, &cap_arg(cpu, cpu.r[1])?, 4096)?;
+    let path = sandboxed(&env.root, &path)?;
+    let flags = cpu.r[2];
+
+    let mut opts = OpenOptions::new();
+    match flags & 3 {
+        O_RDONLY => opts.read(true),
+        O_WRONLY => opts.write(true),
+        O_RDWR => opts.read(true).write(true),
+        _ => return Err(EINVAL),
+    };
+    opts.create(flags & O_CREAT != 0)
+        .truncate(flags & O_TRUNC != 0)
+        .append(flags & O_APPEND != 0);
+
+    let file = opts.open(&path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => ENOENT,
+        std::io::ErrorKind::PermissionDenied => EACCES,
+        _ => EIO,
+    })?;
+
+    let slot = match env.files.iter().position(|f| f.is_none()) {
+        Some(k) => k,
+        None if env.files.len() < 1024 => {
+            env.files.push(None);
+            env.files.len() - 1
+        }
+        None => return Err(EMFILE),
+    };
+    env.files[slot] = Some(file);
+    Ok(Outcome::Return(slot as u64 + 3))
+}
+
+// close(fd); the standard streams stay open
+fn sys_close(env: &mut Env, cpu: &mut CPU, _: &mut Memory) -> SysResult {
+    let fd = cpu.r[1];
+    if fd < 3 {
+        return Ok(Outcome::Return(0));
+    }
+    env.file(fd)?;
+    env.files[fd as usize - 3] = None;
+    Ok(Outcome::Return(0))
+}
+
+// The heap is the gap between the data section and the stack: brk grows up
+// from the bottom of it and mmap hands out pages from the top.
+fn heap(env: &mut Env, cpu: &CPU) -> (u64, u64) {
+    if env.brk.is_none() {
+        env.attach(cpu);
+    }
+    env.brk.unwrap()
+}
+
+// brk(addr) -> new break, with the data capability covering the data section
+// up to it in c5. An address of 0 queries it. Only the capability the loader
+// issued is widened, never whatever the program has put in c[2], and the
+// memory it grows over is zeroed and untagged.
+fn sys_brk(env: &mut Env, cpu: &mut CPU, mem: &mut Memory) -> SysResult {
+    let (brk, top) = heap(env, cpu);
+    let want = cpu.r[1];
+    if want != 0 && want != brk {
+        if want < brk || want > top || want > mem.bytes.len() as u64 {
+            return Err(ENOMEM);
+        }
+        mem.bytes[brk as usize..want as usize].fill(0);
+        mem.clear_tags(brk, want - brk);
+        env.data.length = want - env.data.base;
+        env.brk = Some((want, top));
+    }
+
+    cpu.c[SYS_CAP] = env.data;
+    Ok(Outcome::Return(env.data.base + env.data.length))
+}
+
+// mmap(len) -> address of fresh zeroed read/write memory, with a capability
+// for that range in c5. As with malloc, the bounds are rounded out to the
+// nearest encodable range so the capability can be stored. Mappings are never
+// returned.
+fn sys_mmap(env: &mut Env, cpu: &mut CPU, mem: &mut Memory) -> SysResult {
+    const PAGE: u64 = 4096;
+    let (brk, top) = heap(env, cpu);
+    let len = cpu.r[1];
+    if len == 0 {
+        return Err(EINVAL);
+    }
+    let (_, length) = round_bounds(0, len);
+    let align = alignment(length).max(PAGE);
+    let size = length.checked_next_multiple_of(PAGE).ok_or(ENOMEM)?;
+    let base = top
+        .checked_sub(size)
+        .map(|b| b & !(align - 1))
+        .filter(|b| *b >= brk)
+        .ok_or(ENOMEM)?;
+    if base + size > mem.bytes.len() as u64 {
+        return Err(ENOMEM);
+    }
+
+    mem.bytes[base as usize..(base + size) as usize].fill(0);
+    mem.clear_tags(base, size);
+    env.brk = Some((brk, base));
+
+    cpu.c[SYS_CAP] = Capability {
+        base,
+        length,
+        offset: 0,
+        perms: 0x1B,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    };
+    Ok(Outcome::Return(base))
+}
+
+// malloc(len) -> address of a zeroed object, with a read/write capability
+// bounded to it in c5. The bounds are exactly `len` bytes whenever the
+// compressed format can say so, and otherwise the smallest encodable range
+// around it, so the capability survives being stored to memory.
+fn sys_malloc(env: &mut Env, cpu: &mut CPU, mem: &mut Memory) -> SysResult {
+    let len = cpu.r[1];
+    if len == 0 {
+        return Err(EINVAL);
+    }
+    let (_, length) = round_bounds(0, len);
+    let align = alignment(length).max(CAP_SIZE);
+    let size = length.checked_next_multiple_of(CAP_SIZE).ok_or(ENOMEM)?;
+
+    let base = match reuse(env, size, align) {
+        Some(b) => b,
+        None => match carve(env, cpu, mem, size, align) {
+            Some(b) => b,
+            // Out of room: sweep so the quarantined blocks can be reused.
+            None => {
+                revoke(env, cpu, mem);
+                reuse(env, size, align).ok_or(ENOMEM)?
+            }
+        },
+    };
+
+    mem.bytes[base as usize..(base + size) as usize].fill(0);
+    mem.clear_tags(base, size);
+    env.allocs.insert(base, (length, size));
+
+    cpu.c[SYS_CAP] = Capability {
+        base,
+        length,
+        offset: 0,
+        perms: 0x1B,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    };
+    Ok(Outcome::Return(base))
+}
+
+// Takes a new block off the top of the heap gap, keeping any alignment
+// padding above it for later.
+fn carve(env: &mut Env, cpu: &CPU, mem: &Memory, size: u64, align: u64) -> Option<u64> {
+    let (brk, top) = heap(env, cpu);
+    let base = top.checked_sub(size).map(|b| b & !(align - 1)).filter(|b| *b >= brk)?;
+    if top > mem.bytes.len() as u64 {
+        return None;
+    }
+    if base + size < top {
+        env.spare.push((base + size, top - base - size));
+    }
+    env.brk = Some((brk, base));
+    Some(base)
+}
+
+// First fit over the freed blocks, splitting off what is left on each side.
+fn reuse(env: &mut Env, size: u64, align: u64) -> Option<u64> {
+    let k = env.spare.iter().position(|&(b, s)| {
+        b.checked_next_multiple_of(align).is_some_and(|a| a + size <= b + s)
+    })?;
+    let (b, s) = env.spare.swap_remove(k);
+    let base = b.next_multiple_of(align);
+    if base > b {
+        env.spare.push((b, base - b));
+    }
+    if base + size < b + s {
+        env.spare.push((base + size, b + s - base - size));
+    }
+    Some(base)
+}
+
+// free(c[obj]). The capability must be an unsealed, writable one spanning a
+// whole live malloc object; a narrowed or read-only capability derived from
+// it cannot free it. The object's memory is zeroed, which also untags any capabilities
+// stored in it, and quarantined until a revocation sweep has removed every
+// capability still pointing at it. With the default batch size of zero that
+// sweep happens right away, so a dangling copy faults on its next use.
+fn sys_free(env: &mut Env, cpu: &mut CPU, mem: &mut Memory) -> SysResult {
+    let obj = cap_arg(cpu, cpu.r[1])?;
+    if !obj.valid || obj.sealed {
+        return Err(EFAULT);
+    }
+    let &(length, size) = env.allocs.get(&obj.base).ok_or(EINVAL)?;
+    if obj.length != length || !obj.can_write() {
+        return Err(EINVAL);
+    }
+    env.allocs.remove(&obj.base);
+
+    mem.bytes[obj.base as usize..(obj.base + size) as usize].fill(0);
+    mem.clear_tags(obj.base, size);
+    env.revoker.get_or_insert_with(|| Revoker::new(mem.bytes.len())).paint(obj.base, size);
+    env.quarantine.push((obj.base, size));
+    env.quarantined += size;
+    if env.quarantined > env.revoke_batch {
+        revoke(env, cpu, mem);
+    }
+    Ok(Outcome::Return(0))
+}
+
+// revoke() -> number of capabilities untagged. Sweeps now instead of waiting
+// for the quarantine to fill up.
+fn sys_revoke(env: &mut Env, cpu: &mut CPU, mem: &mut Memory) -> SysResult {
+    Ok(Outcome::Return(revoke(env, cpu, mem)))
+}
+
+// Sweeps for capabilities into quarantined blocks and releases the blocks.
+fn revoke(env: &mut Env, cpu: &mut CPU, mem: &mut Memory) -> u64 {
+    let Some(r) = env.revoker.as_mut() else {
+        return 0;
+    };
+    let n = r.sweep(cpu, mem);
+    r.clear();
+    env.spare.append(&mut env.quarantine);
+    env.quarantined = 0;
+    n
+}
+
+// time() -> nanoseconds since the Unix epoch
+fn sys_time(_: &mut Env, _: &mut CPU, _: &mut Memory) -> SysResult {
+    let t = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|_| EIO)?;
+    Ok(Outcome::Return(t.as_nanos() as u64))
+}
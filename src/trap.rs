@@ -3,8 +3,16 @@ pub enum Trap {
     IllegalInstruction,
     CapViolation,
     OutOfBounds,
+    Misaligned,
+    /// Any integer division or remainder by zero. Signed overflow
+    /// (`i64::MIN / -1`) wraps instead of trapping.
     DivideByZero,
     Syscall(u64),
+    /// A supervisor-only instruction executed in user mode.
+    PrivilegeViolation,
+    /// The cycle counter reached `timecmp`. Only ever delivered to a guest
+    /// handler, never left for the host.
+    TimerInterrupt,
 }
 
 pub fn trap_name(t: Trap) -> &'static str {
@@ -12,7 +20,24 @@ pub fn trap_name(t: Trap) -> &'static str {
         Trap::IllegalInstruction => "Illegal Instruction",
         Trap::CapViolation => "Capability Violation",
         Trap::OutOfBounds => "Out Of Bounds",
+        Trap::Misaligned => "Misaligned Access",
         Trap::DivideByZero => "Divide By Zero",
         Trap::Syscall(_) => "Syscall",
+        Trap::PrivilegeViolation => "Privilege Violation",
+        Trap::TimerInterrupt => "Timer Interrupt",
+    }
+}
+
+/// Number a handler sees as the trap cause. Zero means no trap.
+pub fn trap_cause(t: Trap) -> u64 {
+    match t {
+        Trap::IllegalInstruction => 1,
+        Trap::CapViolation => 2,
+        Trap::OutOfBounds => 3,
+        Trap::Misaligned => 4,
+        Trap::DivideByZero => 5,
+        Trap::Syscall(_) => 6,
+        Trap::PrivilegeViolation => 7,
+        Trap::TimerInterrupt => 8,
     }
 }
@@ -0,0 +1,97 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::isa::GROUP_ALU;
+use hephaestus_isa::trap::Trap;
+
+fn alu(op: u8, a: u64, b: u64) -> u64 {
+    let (mut cpu, mut mem) = machine(&ext(GROUP_ALU, op, 3, 1, 2));
+    cpu.r[1] = a;
+    cpu.r[2] = b;
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    cpu.r[3]
+}
+
+#[test]
+fn and() {
+    assert_eq!(alu(0x0, 0xF0F0, 0xFF00), 0xF000);
+}
+
+#[test]
+fn or() {
+    assert_eq!(alu(0x1, 0xF0F0, 0x0F00), 0xFFF0);
+}
+
+#[test]
+fn xor() {
+    assert_eq!(alu(0x2, 0xF0F0, 0xFF00), 0x0FF0);
+}
+
+#[test]
+fn not() {
+    assert_eq!(alu(0x3, 0x00FF, 0), !0x00FFu64);
+}
+
+#[test]
+fn shl() {
+    assert_eq!(alu(0x4, 1, 63), 1 << 63);
+    assert_eq!(alu(0x4, 1, 64), 1, "shift amount is taken mod 64");
+}
+
+#[test]
+fn shr() {
+    assert_eq!(alu(0x5, 1 << 63, 63), 1);
+    assert_eq!(alu(0x5, u64::MAX, 60), 0xF);
+}
+
+#[test]
+fn sar() {
+    assert_eq!(alu(0x6, 1 << 63, 63), u64::MAX);
+    assert_eq!(alu(0x6, 0x80, 4), 0x8);
+}
+
+fn alu_trap(op: u8, a: u64, b: u64) -> Option<Trap> {
+    let (mut cpu, mut mem) = machine(&ext(GROUP_ALU, op, 3, 1, 2));
+    cpu.r[1] = a;
+    cpu.r[2] = b;
+    run(&mut cpu, &mut mem);
+    cpu.trap
+}
+
+#[test]
+fn divs() {
+    assert_eq!(alu(0x7, -7i64 as u64, 2), -3i64 as u64);
+    assert_eq!(alu(0x7, i64::MIN as u64, -1i64 as u64), i64::MIN as u64);
+}
+
+#[test]
+fn remu() {
+    assert_eq!(alu(0x8, 17, 5), 2);
+    assert_eq!(alu(0x8, -1i64 as u64, 10), 5);
+}
+
+#[test]
+fn rems() {
+    assert_eq!(alu(0x9, -7i64 as u64, 2), -1i64 as u64);
+    assert_eq!(alu(0x9, i64::MIN as u64, -1i64 as u64), 0);
+}
+
+#[test]
+fn division_by_zero_traps() {
+    for op in [0x7, 0x8, 0x9] {
+        assert!(matches!(alu_trap(op, 1, 0), Some(Trap::DivideByZero)));
+    }
+}
+
+#[test]
+fn slt() {
+    assert_eq!(alu(0xA, -1i64 as u64, 0), 1);
+    assert_eq!(alu(0xA, 0, -1i64 as u64), 0);
+}
+
+#[test]
+fn sltu() {
+    assert_eq!(alu(0xB, -1i64 as u64, 0), 0);
+    assert_eq!(alu(0xB, 0, -1i64 as u64), 1);
+}
@@ -0,0 +1,126 @@
+// The assembler is a binary, not part of the library, so its modules are
+// compiled in here directly. They refer to each other through `crate::`,
+// which is why they sit at the root of this test crate.
+#[allow(dead_code)]
+#[path = "../src/assembler/src/lexer.rs"]
+mod lexer;
+#[allow(dead_code)]
+#[path = "../src/assembler/src/parser.rs"]
+mod parser;
+#[allow(dead_code)]
+#[path = "../src/assembler/src/opcodes.rs"]
+mod opcodes;
+#[allow(dead_code)]
+#[path = "../src/assembler/src/emitter.rs"]
+mod emitter;
+#[allow(dead_code)]
+#[path = "../src/assembler/src/pseudo.rs"]
+mod pseudo;
+
+mod common;
+
+use common::*;
+use hephaestus_isa::isa::GROUP_CMEM;
+use hephaestus_isa::trap::Trap;
+
+fn assemble_with_warnings(src: &str) -> (Vec<u16>, Vec<String>) {
+    let toks = lexer::lex(src).unwrap();
+    let ast = parser::parse(&toks).unwrap();
+    let ast = pseudo::expand(&ast).unwrap();
+    emitter::emit(&ast).unwrap()
+}
+
+fn assemble(src: &str) -> Vec<u16> {
+    let (code, warnings) = assemble_with_warnings(src);
+    assert!(warnings.is_empty(), "{warnings:?}");
+    code
+}
+
+#[test]
+fn li_loads_every_width() {
+    let values = [
+        0,
+        7,
+        -8,
+        0x7FFF,
+        -0x8000,
+        0xFFFF,
+        0x1234_5678,
+        0xFFFF_FFFF,
+        -0x8000_0000,
+        0x1_0000_0000,
+        0x1234_5678_9ABC_DEF0,
+        -0x1234_5678_9ABC_DEF0,
+        0x7FFF_FFFF_8000_0000,
+        i64::MAX,
+    ];
+
+    for v in values {
+        let code = assemble(&format!("li r3, {v}\n"));
+        let (mut cpu, mut mem) = machine(&code);
+        cpu.r[3] = 0xDEAD;
+        run(&mut cpu, &mut mem);
+        assert!(!cpu.is_trapped(), "{v:#x}");
+        assert_eq!(cpu.r[3], v as u64, "{v:#x} via {code:04x?}");
+    }
+}
+
+#[test]
+fn li_picks_the_shortest_sequence() {
+    assert_eq!(assemble("li r1, 5").len(), 1);
+    assert_eq!(assemble("li r1, 0xFFFF").len(), 3);
+    assert_eq!(assemble("li r1, 0x10000").len(), 4);
+}
+
+#[test]
+fn capability_relative_operands_round_trip() {
+    assert_eq!(assemble("ld r3, 8(c4)"), [0xB540, base(0x6, 3, 4, 0), 8]);
+    assert_eq!(assemble("ld8u r3, 2(c4)"), ext(GROUP_CMEM, 0x1, 3, 4, 2));
+
+    let code = assemble("ld r3, 8(c4)\nst r3, 0x10(c4)\n");
+    let (mut cpu, mut mem) = machine(&code);
+    cpu.c[4] = cpu.c[2];
+    let d = DATA as usize;
+    mem.bytes[d + 8..d + 16].copy_from_slice(&0x1122_3344_5566_7788u64.to_le_bytes());
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.r[3], 0x1122_3344_5566_7788);
+    assert_eq!(mem.bytes[d + 0x10..d + 0x18], mem.bytes[d + 8..d + 16]);
+}
+
+#[test]
+fn capability_relative_offsets_can_be_negative() {
+    let code = assemble("ld32u r3, -4(c4)\nst8 r3, -256(c4)\n");
+    let (mut cpu, mut mem) = machine(&code);
+    cpu.c[4] = cpu.c[2];
+    cpu.c[4].offset = 0x200;
+    let d = DATA as usize;
+    mem.bytes[d + 0x1FC..d + 0x200].copy_from_slice(&[0xAA, 0xBB, 0xCC, 0xDD]);
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.r[3], 0xDDCC_BBAA);
+    assert_eq!(mem.bytes[d + 0x100], 0xAA);
+
+    // Below the base is still out of bounds.
+    let (mut cpu, mut mem) = machine(&assemble("ld8 r3, -1(c4)"));
+    cpu.c[4] = cpu.c[2];
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+    assert_eq!((cpu.tf.badaddr, cpu.tf.cap), (DATA - 1, 4));
+}
+
+#[test]
+fn writing_r0_is_a_warning_not_an_error() {
+    let (code, warnings) = assemble_with_warnings("addi r1, r0, 1\naddi r0, r1, 2\nld8u r0, 0(r1)\n");
+    assert_eq!(code.len(), 4);
+    assert_eq!(warnings.len(), 2, "{warnings:?}");
+    assert!(warnings[0].starts_with("addi at 0x2 writes r0"), "{}", warnings[0]);
+    assert!(warnings[1].starts_with("ld8u at 0x4 writes r0"), "{}", warnings[1]);
+}
+
+#[test]
+fn reading_r0_is_not_a_warning() {
+    // st and push read their first register, and br compares against it.
+    let (_, warnings) = assemble_with_warnings("st r0, 0(r1)\npush r0\nbr r0, r1, 1\n");
+    assert!(warnings.is_empty(), "{warnings:?}");
+}
@@ -0,0 +1,96 @@
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::devices::block::*;
+use hephaestus_isa::mem::Memory;
+
+const DEV: u64 = 0x10_0000;
+const DESC: u64 = 0x200;
+const BUF: u64 = 0x1000;
+
+fn cap(base: u64, length: u64, perms: u8) -> Capability {
+    Capability { base, length, offset: 0, perms, valid: true, sealed: false, otype: 0 }
+}
+
+fn setup() -> (Memory, MemDisk) {
+    let mut mem = Memory::new(0x4000);
+    let disk = MemDisk::new(4);
+    mem.map(DEV, 0x100, Box::new(BlockDevice::new(Box::new(disk.clone())))).unwrap();
+    (mem, disk)
+}
+
+// Stores `buf` as the descriptor and runs `cmd` on `sector`; returns the
+// status register.
+fn command(mem: &mut Memory, buf: &Capability, sector: u64, cmd: u64) -> u64 {
+    let ram = cap(0, 0x4000, 0x1B);
+    let dev = cap(DEV, 0x100, 0x3);
+    mem.store_cap(DESC, buf, &ram).unwrap();
+    mem.store64(DEV + SECTOR, sector, &dev).unwrap();
+    mem.store64(DEV + BUFFER, DESC, &dev).unwrap();
+    mem.store64(DEV + COMMAND, cmd, &dev).unwrap();
+    mem.load64(DEV + STATUS, &dev).unwrap()
+}
+
+#[test]
+fn reports_capacity() {
+    let (mut mem, _) = setup();
+    assert_eq!(mem.load64(DEV + SECTORS, &cap(DEV, 0x100, 1)).unwrap(), 4);
+}
+
+#[test]
+fn read_copies_a_sector_into_the_buffer() {
+    let (mut mem, disk) = setup();
+    disk.set_sector(2, b"hello");
+    let status = command(&mut mem, &cap(BUF, 512, 0x3), 2, CMD_READ);
+    assert_eq!(status, STATUS_OK);
+    assert_eq!(&mem.bytes[BUF as usize..BUF as usize + 5], b"hello");
+}
+
+#[test]
+fn write_copies_the_buffer_to_a_sector() {
+    let (mut mem, disk) = setup();
+    mem.bytes[BUF as usize..BUF as usize + 3].copy_from_slice(b"abc");
+    let status = command(&mut mem, &cap(BUF, 512, 0x3), 1, CMD_WRITE);
+    assert_eq!(status, STATUS_OK);
+    assert_eq!(&disk.sector(1)[..3], b"abc");
+}
+
+#[test]
+fn dma_is_checked_against_the_buffer_capability() {
+    let (mut mem, disk) = setup();
+    disk.set_sector(0, &[0xAA; 512]);
+
+    // Too short, read-only for a read, write-only for a write.
+    assert_eq!(command(&mut mem, &cap(BUF, 256, 0x3), 0, CMD_READ), STATUS_CAP_FAULT);
+    assert_eq!(command(&mut mem, &cap(BUF, 512, 0x1), 0, CMD_READ), STATUS_CAP_FAULT);
+    assert_eq!(command(&mut mem, &cap(BUF, 512, 0x2), 0, CMD_WRITE), STATUS_CAP_FAULT);
+    assert!(mem.bytes[BUF as usize..BUF as usize + 512].iter().all(|b| *b == 0));
+    assert_eq!(disk.sector(0), vec![0xAA; 512]);
+}
+
+#[test]
+fn forged_descriptor_is_rejected() {
+    let (mut mem, _) = setup();
+    let ram = cap(0, 0x4000, 0x1B);
+    command(&mut mem, &cap(BUF, 512, 0x3), 0, CMD_READ);
+
+    // Overwriting a byte of the descriptor clears its tag.
+    mem.store8(DESC, mem.bytes[DESC as usize], &ram).unwrap();
+    let dev = cap(DEV, 0x100, 0x3);
+    mem.store64(DEV + COMMAND, CMD_READ, &dev).unwrap();
+    assert_eq!(mem.load64(DEV + STATUS, &dev).unwrap(), STATUS_CAP_FAULT);
+}
+
+#[test]
+fn read_clears_tags_in_the_buffer() {
+    let (mut mem, _) = setup();
+    let ram = cap(0, 0x4000, 0x1B);
+    mem.store_cap(BUF + 16, &ram, &ram).unwrap();
+    assert_eq!(command(&mut mem, &cap(BUF, 512, 0x3), 3, CMD_READ), STATUS_OK);
+    assert!(!mem.load_cap(BUF + 16, &ram).unwrap().valid);
+}
+
+#[test]
+fn bad_sector_and_command() {
+    let (mut mem, _) = setup();
+    assert_eq!(command(&mut mem, &cap(BUF, 512, 0x3), 4, CMD_READ), STATUS_IO_ERROR);
+    assert_eq!(command(&mut mem, &cap(BUF, 512, 0x3), 0, 9), STATUS_BAD_COMMAND);
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::isa::GROUP_BRANCH;
+
+// Runs `bxx r1, r2, +1` over an `addi r3, r3, 1` and reports whether the
+// branch skipped it.
+fn taken(op: u8, a: u64, b: u64) -> bool {
+    let [p, w] = ext(GROUP_BRANCH, op, 2, 1, 1);
+    let (mut cpu, mut mem) = machine(&[p, w, base(0x1, 3, 3, 1)]);
+    cpu.r[1] = a;
+    cpu.r[2] = b;
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    cpu.r[3] == 0
+}
+
+#[test]
+fn bne() {
+    assert!(taken(0x0, 1, 2));
+    assert!(!taken(0x0, 2, 2));
+}
+
+#[test]
+fn blt() {
+    assert!(taken(0x1, -1i64 as u64, 0));
+    assert!(!taken(0x1, 0, 0));
+}
+
+#[test]
+fn bge() {
+    assert!(taken(0x2, 0, 0));
+    assert!(!taken(0x2, -1i64 as u64, 0));
+}
+
+#[test]
+fn bltu() {
+    assert!(taken(0x3, 0, -1i64 as u64));
+    assert!(!taken(0x3, -1i64 as u64, 0));
+}
+
+#[test]
+fn bgeu() {
+    assert!(taken(0x4, -1i64 as u64, 0));
+    assert!(!taken(0x4, 0, 1));
+}
+
+#[test]
+fn br_compares_rs1_with_rd_field() {
+    let (mut cpu, mut mem) = machine(&[base(0x7, 2, 1, 1), base(0x1, 3, 3, 1)]);
+    cpu.r[1] = 5;
+    cpu.r[2] = 5;
+    run(&mut cpu, &mut mem);
+    assert_eq!(cpu.r[3], 0);
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::bus::Device;
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::devices::rng::Rng;
+use hephaestus_isa::isa::GROUP_CMEM;
+use hephaestus_isa::mem::Memory;
+use hephaestus_isa::trap::Trap;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const DEV: u64 = 0x10_0000;
+
+type Log = Rc<RefCell<Vec<(char, u64, u64, u64)>>>;
+
+/// Records every access and answers reads with offset + 0x100.
+struct Mock(Log);
+
+impl Device for Mock {
+    fn read(&mut self, offset: u64, size: u64) -> u64 {
+        self.0.borrow_mut().push(('r', offset, size, 0));
+        offset + 0x100
+    }
+
+    fn write(&mut self, offset: u64, size: u64, val: u64) {
+        self.0.borrow_mut().push(('w', offset, size, val));
+    }
+}
+
+fn with_mock(mem: &mut Memory) -> Log {
+    let log = Log::default();
+    mem.map(DEV, 0x100, Box::new(Mock(log.clone()))).unwrap();
+    log
+}
+
+fn dev_cap(perms: u8) -> Capability {
+    Capability { base: DEV, length: 0x100, offset: 0, perms, valid: true, sealed: false, otype: 0 }
+}
+
+#[test]
+fn guest_stores_and_loads_reach_the_device() {
+    // st32 r1, 4(c4) ; ld16u r2, 2(c4)
+    let mut code = ext(GROUP_CMEM, 0x9, 1, 4, 4).to_vec();
+    code.extend(ext(GROUP_CMEM, 0x3, 2, 4, 2));
+    let (mut cpu, mut mem) = machine(&code);
+    let log = with_mock(&mut mem);
+    cpu.c[4] = dev_cap(3);
+    cpu.r[1] = 0xDEAD_BEEF;
+    run(&mut cpu, &mut mem);
+
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.r[2], 0x102);
+    assert_eq!(*log.borrow(), vec![('w', 4, 4, 0xDEAD_BEEF), ('r', 2, 2, 0)]);
+}
+
+#[test]
+fn device_access_needs_a_covering_capability() {
+    let mut mem = Memory::new(0x1000);
+    let log = with_mock(&mut mem);
+    let ram = Capability { base: 0, length: 0x1000, ..dev_cap(3) };
+
+    assert!(matches!(mem.store8(DEV, 1, &ram), Err(Trap::OutOfBounds)));
+    assert!(matches!(mem.load8(DEV, &dev_cap(2)), Err(Trap::CapViolation)));
+    assert!(log.borrow().is_empty());
+}
+
+#[test]
+fn unmapped_and_straddling_accesses_fault() {
+    let mut mem = Memory::new(0x1000);
+    let log = with_mock(&mut mem);
+    let all = Capability { base: 0, length: u64::MAX, ..dev_cap(3) };
+
+    assert!(matches!(mem.load8(0x2000, &all), Err(Trap::OutOfBounds)));
+    assert!(matches!(mem.load64(DEV + 0xFC, &all), Err(Trap::OutOfBounds)));
+    assert!(matches!(mem.load32(0xFFE, &all), Err(Trap::OutOfBounds)));
+    assert!(log.borrow().is_empty());
+}
+
+#[test]
+fn capabilities_and_code_stay_in_ram() {
+    let mut mem = Memory::new(0x1000);
+    with_mock(&mut mem);
+    let all = Capability { base: 0, length: u64::MAX, ..dev_cap(0x1F) };
+
+    assert!(matches!(mem.store_cap(DEV, &all, &all), Err(Trap::OutOfBounds)));
+    assert!(matches!(mem.load_cap(DEV, &all), Err(Trap::OutOfBounds)));
+    assert!(matches!(mem.fetch16(DEV, &all), Err(Trap::OutOfBounds)));
+}
+
+#[test]
+fn overlapping_mappings_are_rejected() {
+    let mut mem = Memory::new(0x1000);
+    with_mock(&mut mem);
+    assert!(mem.map(0x800, 0x100, Box::new(Rng::new(1))).is_err());
+    assert!(mem.map(DEV + 0x80, 0x100, Box::new(Rng::new(1))).is_err());
+    assert!(mem.map(DEV + 0x100, 0x100, Box::new(Rng::new(1))).is_ok());
+}
+
+#[test]
+fn rng_is_reproducible() {
+    let mut a = Rng::new(42);
+    let mut b = Rng::new(7);
+    b.write(0, 8, 42);
+    let xs: Vec<u64> = (0..4).map(|_| a.read(0, 8)).collect();
+    let ys: Vec<u64> = (0..4).map(|_| b.read(0, 8)).collect();
+    assert_eq!(xs, ys);
+    assert_ne!(xs[0], xs[1]);
+}
+
+#[test]
+fn narrow_rng_reads_take_the_low_bytes() {
+    for size in [1, 2, 4] {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let wide = a.read(0, 8);
+        let narrow = b.read(0, size);
+        assert_eq!(narrow, wide & ((1 << (8 * size)) - 1), "size {size}");
+    }
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::cap_compressed::{compress, decompress, exact, representable, round_bounds};
+
+const CASES: usize = 20_000;
+
+fn random_cap(rng: &mut Rng) -> Capability {
+    let (base, length) = round_bounds(rng.scaled(), rng.scaled());
+    let length = length.min(u64::MAX - base);
+    let (base, length) = round_bounds(base, length);
+    Capability {
+        base,
+        length,
+        offset: if length == 0 { 0 } else { rng.next() % length },
+        perms: rng.next() as u8,
+        valid: true,
+        sealed: rng.next() & 1 == 0,
+        otype: rng.next() & 0xFFFF,
+    }
+}
+
+#[test]
+fn rounding_covers_the_request_and_is_exact() {
+    let mut rng = Rng(1);
+    for _ in 0..CASES {
+        let base = rng.scaled();
+        let length = rng.scaled().min(u64::MAX - base);
+        let (b, l) = round_bounds(base, length);
+        assert!(b <= base, "{:#x}+{:#x} rounded to {:#x}+{:#x}", base, length, b, l);
+        assert!(b as u128 + l as u128 >= base as u128 + length as u128);
+        assert!(exact(b, l));
+    }
+}
+
+#[test]
+fn small_bounds_are_exact() {
+    let mut rng = Rng(2);
+    for _ in 0..CASES {
+        let base = rng.next();
+        let length = rng.next() % 0x4000;
+        if base.checked_add(length).is_some() {
+            assert!(exact(base, length), "{:#x}+{:#x}", base, length);
+        }
+    }
+}
+
+#[test]
+fn in_bounds_capabilities_round_trip() {
+    let mut rng = Rng(3);
+    for _ in 0..CASES {
+        let c = random_cap(&mut rng);
+        let (bytes, fits) = compress(&c);
+        assert!(fits, "{:?}", c);
+        assert_eq!(decompress(&bytes, true), c);
+    }
+}
+
+#[test]
+fn representable_cursors_round_trip() {
+    let mut rng = Rng(4);
+    for _ in 0..CASES {
+        let c = random_cap(&mut rng);
+        let moved = Capability { offset: c.offset.wrapping_add(rng.scaled()), ..c };
+        let (bytes, fits) = compress(&moved);
+        assert_eq!(fits, representable(&c, moved.offset));
+        if fits {
+            assert_eq!(decompress(&bytes, true), moved);
+        }
+    }
+}
+
+#[test]
+fn inexact_capabilities_are_not_marked_exact() {
+    let c = Capability {
+        base: 0x1001,
+        length: 0x12345,
+        offset: 0,
+        perms: 3,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    };
+    assert!(!exact(c.base, c.length));
+    assert!(!compress(&c).1);
+}
+
+#[test]
+fn cap_offset_untags_outside_representable_region() {
+    let mut code = wide(0, 0xF, 4, 3, 0x10_0000);
+    code.extend(wide(0, 0xF, 5, 3, 0x20));
+    let (mut cpu, mut mem) = machine(&code);
+    cpu.c[3] = Capability {
+        base: 0x2000,
+        length: 0x100,
+        offset: 0,
+        perms: 3,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    };
+    run(&mut cpu, &mut mem);
+
+    assert!(!cpu.is_trapped());
+    assert!(!cpu.c[4].valid, "far cursor must lose its tag");
+    assert!(cpu.c[5].valid);
+    assert_eq!(cpu.c[5].offset, 0x20);
+}
+
+#[test]
+fn tags_survive_memory_only_when_exact() {
+    use hephaestus_isa::mem::Memory;
+
+    let mut mem = Memory::new(0x1000);
+    let auth = Capability {
+        base: 0,
+        length: 0x1000,
+        offset: 0,
+        perms: 0x1B,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    };
+    let good = Capability { base: 0x100, length: 0x40, offset: 8, ..auth };
+    let odd = Capability { base: 0x101, length: 0x12345, offset: 0, ..auth };
+
+    mem.store_cap(0x200, &good, &auth).unwrap();
+    mem.store_cap(0x210, &odd, &auth).unwrap();
+    assert_eq!(mem.load_cap(0x200, &auth).unwrap(), good);
+    assert!(!mem.load_cap(0x210, &auth).unwrap().valid);
+}
+
+#[test]
+fn saturated_lengths_are_not_exact() {
+    // Rounding [0, u64::MAX) gives 2^64 bytes, which round_bounds saturates
+    // back to u64::MAX. The saturated result must not count as exact.
+    assert_eq!(round_bounds(0, u64::MAX), (0, u64::MAX));
+    assert!(!exact(0, u64::MAX));
+    assert!(!exact(1 << 40, u64::MAX - (1 << 40)));
+    assert!(exact(0, 1 << 63));
+
+    use hephaestus_isa::mem::Memory;
+
+    // So a capability over the whole address space, like the root, is
+    // stored untagged rather than decoding to different bounds.
+    let mut mem = Memory::new(0x1000);
+    let root = Capability {
+        base: 0,
+        length: u64::MAX,
+        offset: 0,
+        perms: 0xFF,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    };
+    mem.store_cap(0x100, &root, &root).unwrap();
+    assert!(!mem.load_cap(0x100, &root).unwrap().valid);
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::isa::{GROUP_CMEM, GROUP_MEM};
+use hephaestus_isa::trap::Trap;
+
+const CAP_LOAD: u8 = 0xB;
+const CAP_STORE: u8 = 0xC;
+
+fn object() -> Capability {
+    Capability {
+        base: DATA + 0x800,
+        length: 0x40,
+        offset: 0x10,
+        perms: 0x03,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    }
+}
+
+#[test]
+fn store_then_load_keeps_the_tag() {
+    // cap.store c4, 0(r1) ; cap.load c5, 0(r1)
+    let mut code = ext(GROUP_MEM, CAP_STORE, 4, 1, 0).to_vec();
+    code.extend(ext(GROUP_MEM, CAP_LOAD, 5, 1, 0));
+    let (mut cpu, mut mem) = machine(&code);
+    cpu.r[1] = DATA + 0x20;
+    cpu.c[4] = object();
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.c[5], object());
+}
+
+#[test]
+fn partial_overwrite_clears_the_tag() {
+    for (op, size, at) in [(0x7, 1u64, 0u64), (0x8, 2, 6), (0x9, 4, 12), (0xA, 8, 8)] {
+        // st r3, 0(r4) ; cap.load c5, 0(r1)
+        let mut code = ext(GROUP_MEM, op, 3, 4, 0).to_vec();
+        code.extend(ext(GROUP_MEM, CAP_LOAD, 5, 1, 0));
+        let (mut cpu, mut mem) = machine(&code);
+        mem.store_cap(DATA, &object(), &cpu.c[2]).unwrap();
+        let before = mem.bytes[DATA as usize..DATA as usize + 16].to_vec();
+        cpu.r[1] = DATA;
+        cpu.r[4] = DATA + at;
+        cpu.r[3] = 0;
+        run(&mut cpu, &mut mem);
+
+        assert!(!cpu.is_trapped());
+        assert!(!cpu.c[5].valid, "{size}-byte store at +{at}");
+        assert!(!mem.tags[DATA as usize / 16]);
+        // Only the stored bytes changed; the rest of the encoding is intact.
+        let after = &mem.bytes[DATA as usize..DATA as usize + 16];
+        for (k, (a, b)) in after.iter().zip(&before).enumerate() {
+            if (k as u64) < at || k as u64 >= at + size {
+                assert_eq!(a, b);
+            }
+        }
+    }
+}
+
+#[test]
+fn store_into_the_next_granule_keeps_the_tag() {
+    let (mut cpu, mut mem) = machine(&ext(GROUP_MEM, 0xA, 3, 1, 0));
+    mem.store_cap(DATA, &object(), &cpu.c[2]).unwrap();
+    cpu.r[1] = DATA + 16;
+    run(&mut cpu, &mut mem);
+    assert!(mem.tags[DATA as usize / 16]);
+    assert_eq!(mem.load_cap(DATA, &cpu.c[2]).unwrap(), object());
+}
+
+#[test]
+fn loading_plain_data_gives_an_untagged_capability() {
+    let (mut cpu, mut mem) = machine(&ext(GROUP_CMEM, CAP_LOAD, 5, 2, 0));
+    mem.bytes[DATA as usize..DATA as usize + 16].fill(0xFF);
+    cpu.c[5] = object();
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert!(!cpu.c[5].valid);
+}
+
+#[test]
+fn cap_load_needs_the_load_capability_permission() {
+    let (mut cpu, mut mem) = machine(&ext(GROUP_CMEM, CAP_LOAD, 5, 4, 0));
+    mem.store_cap(DATA, &object(), &cpu.c[2]).unwrap();
+    cpu.c[4] = Capability { perms: 0x13, ..cpu.c[2] };
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::CapViolation)));
+    assert_eq!((cpu.tf.badaddr, cpu.tf.cap), (DATA, 4));
+    assert!(!cpu.c[5].valid);
+}
+
+#[test]
+fn cap_store_needs_the_store_capability_permission() {
+    // Untagged values are refused too, so a missing permission is never
+    // silently ignored.
+    for val in [object(), Capability::null()] {
+        let (mut cpu, mut mem) = machine(&ext(GROUP_CMEM, CAP_STORE, 5, 4, 0));
+        mem.bytes[DATA as usize..DATA as usize + 16].fill(0xAA);
+        cpu.c[4] = Capability { perms: 0x0B, ..cpu.c[2] };
+        cpu.c[5] = val;
+        run(&mut cpu, &mut mem);
+        assert!(matches!(cpu.trap, Some(Trap::CapViolation)));
+        assert_eq!((cpu.tf.badaddr, cpu.tf.cap), (DATA, 4));
+        assert!(mem.bytes[DATA as usize..DATA as usize + 16].iter().all(|b| *b == 0xAA));
+    }
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::isa::GROUP_CAP;
+use hephaestus_isa::trap::Trap;
+
+const SETBOUNDS: u8 = 0x0;
+const ANDPERM: u8 = 0x1;
+
+// Runs one capability-group instruction `op c4, c3, r5` with c3 = src and
+// r5 = arg, returning the CPU afterwards.
+fn derive(op: u8, src: Capability, arg: u64) -> hephaestus_isa::cpu::CPU {
+    let (mut cpu, mut mem) = machine(&ext(GROUP_CAP, op, 4, 3, 5));
+    cpu.c[3] = src;
+    cpu.r[5] = arg;
+    run(&mut cpu, &mut mem);
+    cpu
+}
+
+fn root() -> Capability {
+    Capability {
+        base: 0x2000,
+        length: 0x1000,
+        offset: 0,
+        perms: 0x87,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    }
+}
+
+fn within(inner: &Capability, outer: &Capability) -> bool {
+    inner.base >= outer.base
+        && inner.base + inner.length <= outer.base + outer.length
+        && inner.perms & !outer.perms == 0
+}
+
+#[test]
+fn setbounds_narrows_from_cursor() {
+    let cpu = derive(SETBOUNDS, Capability { offset: 0x10, ..root() }, 0x20);
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.c[4].base, 0x2010);
+    assert_eq!(cpu.c[4].length, 0x20);
+    assert_eq!(cpu.c[4].offset, 0);
+}
+
+#[test]
+fn setbounds_cannot_grow() {
+    let cpu = derive(SETBOUNDS, Capability { offset: 0x10, ..root() }, 0x1000);
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+    assert!(!cpu.c[4].valid);
+}
+
+#[test]
+fn setbounds_rejects_cursor_past_end() {
+    let cpu = derive(SETBOUNDS, Capability { offset: 0x2000, ..root() }, 0);
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+}
+
+#[test]
+fn andperm_only_clears() {
+    let cpu = derive(ANDPERM, Capability { perms: 0x01, ..root() }, 0xFF);
+    assert_eq!(cpu.c[4].perms, 0x01);
+
+    let cpu = derive(ANDPERM, root(), 0x02);
+    assert_eq!(cpu.c[4].perms, 0x02);
+}
+
+#[test]
+fn derivation_needs_valid_unsealed_source() {
+    for op in [SETBOUNDS, ANDPERM] {
+        let cpu = derive(op, Capability { valid: false, ..root() }, 0);
+        assert!(matches!(cpu.trap, Some(Trap::CapViolation)));
+
+        let cpu = derive(op, Capability { sealed: true, ..root() }, 0);
+        assert!(matches!(cpu.trap, Some(Trap::CapViolation)));
+    }
+}
+
+// Chains thousands of random derivations, including hostile cursors and
+// lengths, and checks that no successful result ever exceeds its source.
+#[test]
+fn random_derivations_are_monotonic() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    let mut next = move || rng.next();
+
+    let origin = root();
+    let mut cur = origin;
+
+    for _ in 0..5000 {
+        let r = next();
+        let src = Capability { offset: if r & 1 == 0 { next() % (cur.length + 2) } else { next() }, ..cur };
+        let (op, arg) = if r & 2 == 0 {
+            (SETBOUNDS, if r & 4 == 0 { next() % (cur.length + 2) } else { next() })
+        } else {
+            (ANDPERM, next())
+        };
+
+        let cpu = derive(op, src, arg);
+        if cpu.is_trapped() {
+            assert!(!cpu.c[4].valid, "a trapping derivation must not write its result");
+            continue;
+        }
+
+        let out = cpu.c[4];
+        assert!(within(&out, &src), "{:?} escaped {:?}", out, src);
+        assert!(within(&out, &origin));
+        // Keep chaining, restarting from the root once the chain bottoms out.
+        cur = if out.length == 0 || r & 0xF0 == 0 { origin } else { out };
+    }
+}
+
+#[test]
+fn base_cap_ops_reject_registers_past_c7() {
+    // cap.null c8; cap.copy c0, c9; cap.offset c2, c8, 0; cap.offset c8, c2, 0
+    for w in [0xD800, 0xE090, 0xF280, 0xF820] {
+        let (mut cpu, mut mem) = machine(&[w]);
+        run(&mut cpu, &mut mem);
+        assert!(matches!(cpu.trap, Some(Trap::IllegalInstruction)), "{w:#x}");
+    }
+}
@@ -0,0 +1,139 @@
+#![allow(dead_code)]
+
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::cpu::CPU;
+use hephaestus_isa::mem::Memory;
+use hephaestus_isa::syscall::{Env, Outcome, Syscalls};
+use hephaestus_isa::trap::Trap;
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+pub const TEXT: u64 = 0x1000;
+pub const DATA: u64 = 0x2000;
+pub const DATA_SIZE: u64 = 0x1000;
+
+pub fn base(op: u8, rd: u8, rs1: u8, low: u8) -> u16 {
+    ((op as u16) << 12) | ((rd as u16) << 8) | ((rs1 as u16) << 4) | (low as u16 & 0xF)
+}
+
+/// An extension-group instruction with no trailing immediate.
+pub fn ext(group: u8, op: u8, rd: u8, rs1: u8, low: u8) -> [u16; 2] {
+    [0xB000 | ((group as u16) << 8), base(op, rd, rs1, low)]
+}
+
+/// Loads `code` at TEXT with the PCC and c[1] covering it in supervisor mode
+/// and a read/write c[2] over DATA.
+pub fn machine(code: &[u16]) -> (CPU, Memory) {
+    let mut cpu = CPU::new();
+    let mut mem = Memory::new(0x10000);
+
+    for (k, w) in code.iter().enumerate() {
+        let a = TEXT as usize + 2 * k;
+        mem.bytes[a..a + 2].copy_from_slice(&w.to_le_bytes());
+    }
+
+    cpu.c[1] = Capability {
+        base: TEXT,
+        length: 2 * code.len() as u64,
+        offset: 0,
+        perms: 0x24,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    };
+    cpu.pcc = cpu.c[1];
+    cpu.c[2] = Capability {
+        base: DATA,
+        length: DATA_SIZE,
+        offset: 0,
+        perms: 0x1B,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    };
+
+    (cpu, mem)
+}
+
+/// Steps until the CPU traps or runs off the end of the loaded code.
+pub fn run(cpu: &mut CPU, mem: &mut Memory) {
+    let end = cpu.c[1].base + cpu.c[1].length;
+    while !cpu.is_trapped() && cpu.pc() < end {
+        cpu.step(mem);
+    }
+}
+
+/// An instruction carrying a full 64-bit trailing immediate.
+pub fn wide(group: u8, op: u8, rd: u8, rs1: u8, imm: i64) -> Vec<u16> {
+    let mut w = vec![0xB0C0 | ((group as u16) << 8), base(op, rd, rs1, 0)];
+    for k in 0..4 {
+        w.push((imm as u64 >> (16 * k)) as u16);
+    }
+    w
+}
+
+/// Deterministic xorshift64 generator for property tests.
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A value with a random bit width, so small and huge numbers both show up.
+    pub fn scaled(&mut self) -> u64 {
+        let bits = self.next() % 65;
+        if bits == 64 { self.next() } else { self.next() & ((1 << bits) - 1) }
+    }
+}
+
+/// A Write sink the test can read back after handing it to an Env.
+#[derive(Clone, Default)]
+pub struct Sink(pub Rc<RefCell<Vec<u8>>>);
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn env(stdin: &'static [u8]) -> (Env, Sink) {
+    let out = Sink::default();
+    let mut env = Env::new(std::env::temp_dir());
+    env.stdin = Box::new(stdin);
+    env.stdout = Box::new(out.clone());
+    (env, out)
+}
+
+/// `syscall r9`
+pub const SYSCALL: u16 = 0xC090;
+
+/// Runs the program, dispatching syscalls, until it exits, faults or ends.
+pub fn run_sys(cpu: &mut CPU, mem: &mut Memory, sys: &mut Syscalls) -> Option<u64> {
+    let end = cpu.c[1].base + cpu.c[1].length;
+    while cpu.pc() < end {
+        cpu.step(mem);
+        match cpu.trap.take() {
+            None => {}
+            Some(Trap::Syscall(n)) => {
+                if let Outcome::Exit(code) = sys.dispatch(n, cpu, mem) {
+                    return Some(code);
+                }
+            }
+            Some(t) => {
+                cpu.trap = Some(t);
+                return None;
+            }
+        }
+    }
+    None
+}
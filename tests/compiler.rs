@@ -0,0 +1,60 @@
+// The compiler is not part of the library either. Its modules refer to each
+// other through `super::`, so they sit at the root of this test crate; its
+// mod.rs is left out because it drives the assembler binary.
+#[allow(dead_code)]
+#[path = "../src/compiler/lexer.rs"]
+mod lexer;
+#[allow(dead_code)]
+#[path = "../src/compiler/parser.rs"]
+mod parser;
+#[allow(dead_code)]
+#[path = "../src/compiler/ast.rs"]
+mod ast;
+#[allow(dead_code)]
+#[path = "../src/compiler/ir.rs"]
+mod ir;
+#[allow(dead_code)]
+#[path = "../src/compiler/regalloc.rs"]
+mod regalloc;
+#[allow(dead_code)]
+#[path = "../src/compiler/codegen.rs"]
+mod codegen;
+
+mod common;
+
+use common::*;
+use hephaestus_isa::trap::Trap;
+
+fn compile(src: &str) -> Vec<u16> {
+    let toks = lexer::lex(src).unwrap();
+    let ast = parser::parse(&toks).unwrap();
+    let ir = ir::lower_ast(&ast).unwrap();
+    codegen::generate(&ir).unwrap()
+}
+
+// Runs the program up to the exit syscall and returns r1, the exit code.
+fn result(src: &str) -> u64 {
+    let (mut cpu, mut mem) = machine(&compile(src));
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::Syscall(0))));
+    cpu.r[1]
+}
+
+#[test]
+fn constants_wider_than_a_nibble_survive() {
+    assert_eq!(result("fn main() -> i64 {\n let x = 1000\n return x\n}\n"), 1000);
+}
+
+#[test]
+fn constants_load_at_every_width() {
+    for v in [0, 7, 8, 1000, 0xFFFF, 0x12345, 0xFFFF_FFFF, 0x1234_5678_0000, 0x1234_5678_9ABC_DEF0, i64::MAX] {
+        let src = format!("fn main() -> i64 {{\n let x = {v}\n return x\n}}\n");
+        assert_eq!(result(&src), v as u64, "{v:#x}");
+    }
+}
+
+#[test]
+fn small_constants_take_one_word() {
+    // addi, the move into r1 and the exit syscall.
+    assert_eq!(compile("fn main() -> i64 {\n return 5\n}\n").len(), 3);
+}
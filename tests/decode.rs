@@ -0,0 +1,71 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::cpu::PCC_INDEX;
+use hephaestus_isa::decode::{decode, inst_len};
+use hephaestus_isa::isa::{GROUP_BASE, GROUP_IMM, OP_EXT};
+use hephaestus_isa::trap::Trap;
+
+#[test]
+fn ret_is_a_plain_word() {
+    assert_eq!(inst_len(0xB000), 2);
+    let i = decode(&[0xB000]).unwrap();
+    assert_eq!((i.group, i.opcode, i.len), (GROUP_BASE, OP_EXT, 2));
+}
+
+#[test]
+fn prefix_sizes_select_the_immediate_width() {
+    // addi r1, r2, imm in each size; imm4 is 7 but a trailing immediate wins.
+    let word = base(0x1, 1, 2, 7);
+    let cases: [(&[u16], u64, i64); 4] = [
+        (&[0xB000 | 0x100, word], 4, 7),
+        (&[0xB040, word, 0xFFFE], 6, -2),
+        (&[0xB080, word, 0x5678, 0x8234], 8, 0x8234_5678u32 as i32 as i64),
+        (&[0xB0C0, word, 0x4444, 0x3333, 0x2222, 0x1111], 12, 0x1111_2222_3333_4444),
+    ];
+
+    for (words, len, imm) in cases {
+        assert_eq!(inst_len(words[0]), len);
+        let i = decode(words).unwrap();
+        assert_eq!((i.opcode, i.rd, i.rs1, i.len, i.imm), (0x1, 1, 2, len, imm), "{words:x?}");
+    }
+    assert_eq!(decode(&[0xB100, word]).unwrap().group, GROUP_IMM);
+}
+
+#[test]
+fn reserved_prefix_bits_are_illegal() {
+    for low in [0x01, 0x20, 0x3F] {
+        let r = decode(&[0xB040 | low, base(0x1, 1, 0, 0), 0]);
+        assert!(matches!(r, Err(Trap::IllegalInstruction)), "{low:#x}");
+    }
+}
+
+#[test]
+fn prefixes_do_not_nest() {
+    assert!(matches!(decode(&[0xB040, 0xB100, 0]), Err(Trap::IllegalInstruction)));
+}
+
+#[test]
+fn truncated_prefix_is_rejected() {
+    assert!(matches!(decode(&[0xB040, base(0x1, 1, 0, 0)]), Err(Trap::IllegalInstruction)));
+    assert!(matches!(decode(&[0xB100]), Err(Trap::IllegalInstruction)));
+    assert!(matches!(decode(&[]), Err(Trap::IllegalInstruction)));
+
+    // In memory, the missing words lie past the end of the code capability.
+    let (mut cpu, mut mem) = machine(&[0xB040, base(0x1, 1, 0, 0)]);
+    cpu.step(&mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+    assert_eq!((cpu.tf.cap, cpu.tf.badaddr), (PCC_INDEX, TEXT + 4));
+}
+
+#[test]
+fn st_stores_the_rd_field_register() {
+    // st r3, 8(r1)
+    let (mut cpu, mut mem) = machine(&[0xB040, base(0x6, 3, 1, 0), 8]);
+    cpu.r[1] = DATA;
+    cpu.r[3] = 0x1122_3344;
+    cpu.r[8] = 0xBAD;
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert_eq!(mem.bytes[DATA as usize + 8..DATA as usize + 12], [0x44, 0x33, 0x22, 0x11]);
+}
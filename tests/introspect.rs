@@ -0,0 +1,64 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::isa::GROUP_CAP;
+use hephaestus_isa::trap::Trap;
+
+/// Reads every field of c4 with cap.getbase .. cap.gettype into r1..r8.
+fn fields(c: Capability) -> [u64; 8] {
+    let mut code = Vec::new();
+    for (k, op) in (0x6..=0xD).enumerate() {
+        code.extend(ext(GROUP_CAP, op, k as u8 + 1, 4, 0));
+    }
+    let (mut cpu, mut mem) = machine(&code);
+    cpu.c[4] = c;
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    cpu.r[1..9].try_into().unwrap()
+}
+
+fn sample() -> Capability {
+    Capability {
+        base: 0x2000,
+        length: 0x300,
+        offset: 0x48,
+        perms: 0x1B,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    }
+}
+
+#[test]
+fn getters_read_each_field() {
+    assert_eq!(fields(sample()), [0x2000, 0x300, 0x48, 0x1B, 1, 0x2048, 0, 0]);
+}
+
+#[test]
+fn getters_report_sealing() {
+    let c = Capability { sealed: true, otype: 0x77, ..sample() };
+    assert_eq!(fields(c), [0x2000, 0x300, 0x48, 0x1B, 1, 0x2048, 1, 0x77]);
+}
+
+#[test]
+fn getters_work_on_untagged_capabilities() {
+    // Reading the fields of a cleared capability is allowed; only the tag
+    // tells it apart.
+    let c = Capability { valid: false, ..sample() };
+    assert_eq!(fields(c), [0x2000, 0x300, 0x48, 0x1B, 0, 0x2048, 0, 0]);
+    assert_eq!(fields(Capability::null()), [0; 8]);
+}
+
+#[test]
+fn address_wraps_past_the_top() {
+    let c = Capability { base: u64::MAX - 1, offset: 3, ..sample() };
+    assert_eq!(fields(c)[5], 1);
+}
+
+#[test]
+fn getters_reject_capability_registers_past_c7() {
+    let (mut cpu, mut mem) = machine(&ext(GROUP_CAP, 0x6, 1, 8, 0));
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::IllegalInstruction)));
+}
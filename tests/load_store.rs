@@ -0,0 +1,87 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::isa::{GROUP_CMEM, GROUP_MEM};
+use hephaestus_isa::trap::Trap;
+
+/// Runs one MEM-group load of the bytes `0x80 0x81 .. 0x87` at DATA.
+fn load(op: u8) -> u64 {
+    let (mut cpu, mut mem) = machine(&ext(GROUP_MEM, op, 3, 1, 0));
+    for k in 0..8 {
+        mem.bytes[DATA as usize + k] = 0x80 + k as u8;
+    }
+    cpu.r[1] = DATA;
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped(), "op {op:#x}");
+    cpu.r[3]
+}
+
+#[test]
+fn loads_sign_or_zero_extend_by_width() {
+    assert_eq!(load(0x0), 0xFFFF_FFFF_FFFF_FF80);
+    assert_eq!(load(0x1), 0x80);
+    assert_eq!(load(0x2), 0xFFFF_FFFF_FFFF_8180);
+    assert_eq!(load(0x3), 0x8180);
+    assert_eq!(load(0x4), 0xFFFF_FFFF_8382_8180);
+    assert_eq!(load(0x5), 0x8382_8180);
+    assert_eq!(load(0x6), 0x8786_8584_8382_8180);
+}
+
+#[test]
+fn positive_values_are_not_sign_extended() {
+    let (mut cpu, mut mem) = machine(&ext(GROUP_MEM, 0x2, 3, 1, 0));
+    mem.bytes[DATA as usize] = 0xFF;
+    mem.bytes[DATA as usize + 1] = 0x7F;
+    cpu.r[1] = DATA;
+    run(&mut cpu, &mut mem);
+    assert_eq!(cpu.r[3], 0x7FFF);
+}
+
+#[test]
+fn stores_write_only_their_width() {
+    for (op, size) in [(0x7, 1), (0x8, 2), (0x9, 4), (0xA, 8)] {
+        let (mut cpu, mut mem) = machine(&ext(GROUP_MEM, op, 3, 1, 0));
+        mem.bytes[DATA as usize..DATA as usize + 16].fill(0xEE);
+        cpu.r[1] = DATA;
+        cpu.r[3] = 0x0807_0605_0403_0201;
+        run(&mut cpu, &mut mem);
+        assert!(!cpu.is_trapped());
+
+        let got = &mem.bytes[DATA as usize..DATA as usize + 16];
+        for (k, b) in got.iter().enumerate() {
+            let want = if k < size { k as u8 + 1 } else { 0xEE };
+            assert_eq!(*b, want, "op {op:#x} byte {k}");
+        }
+    }
+}
+
+#[test]
+fn access_straddling_the_end_faults() {
+    let end = DATA + DATA_SIZE;
+    for (op, size) in [(0x2, 2u64), (0x4, 4), (0x6, 8), (0x8, 2), (0x9, 4), (0xA, 8)] {
+        let (mut cpu, mut mem) = machine(&ext(GROUP_MEM, op, 3, 1, 0));
+        cpu.r[1] = end - size + 1;
+        cpu.r[3] = u64::MAX;
+        run(&mut cpu, &mut mem);
+        assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)), "op {op:#x}");
+        assert_eq!((cpu.tf.badaddr, cpu.tf.cap), (end - size + 1, 2));
+        assert!(mem.bytes[(end - size + 1) as usize..end as usize].iter().all(|b| *b == 0));
+    }
+
+    // The last whole access still fits.
+    let (mut cpu, mut mem) = machine(&ext(GROUP_MEM, 0x6, 3, 1, 0));
+    cpu.r[1] = end - 8;
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+}
+
+#[test]
+fn capability_relative_access_straddling_the_end_faults() {
+    // ld32 r3, 0(c4) with c4 two bytes short of the end of its bounds
+    let (mut cpu, mut mem) = machine(&ext(GROUP_CMEM, 0x4, 3, 4, 0));
+    cpu.c[4] = cpu.c[2];
+    cpu.c[4].offset = DATA_SIZE - 2;
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+    assert_eq!((cpu.tf.badaddr, cpu.tf.cap), (DATA + DATA_SIZE - 2, 4));
+}
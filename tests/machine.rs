@@ -0,0 +1,130 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::devices::MMIO_BASE;
+use hephaestus_isa::devices::uart::BufferSerial;
+use hephaestus_isa::isa::{GROUP_CAP, GROUP_CMEM};
+use hephaestus_isa::machine::{Config, Machine, StopReason};
+use hephaestus_isa::syscall::{SYS_EXIT, SYS_MALLOC, SYS_WRITE};
+use hephaestus_isa::trap::Trap;
+
+/// An .oslbin image with `code` at TEXT, entered at its start, and `data` at DATA.
+fn image(code: &[u16], data: &[u8]) -> Vec<u8> {
+    let mut img = Vec::new();
+    for v in [TEXT, TEXT, 2 * code.len() as u64, DATA, data.len() as u64] {
+        img.extend(v.to_le_bytes());
+    }
+    img.extend(code.iter().flat_map(|w| w.to_le_bytes()));
+    img.extend(data);
+    img
+}
+
+fn boot(code: &[u16], data: &[u8]) -> (Machine, Sink) {
+    let (env, out) = env(b"");
+    let config = Config { mem_size: 0x20000, env, ..Config::default() };
+    let mut m = Machine::new(config).unwrap();
+    m.load(&image(code, data)).unwrap();
+    (m, out)
+}
+
+#[test]
+fn runs_until_exit() {
+    // write(1, c2, 2); exit(bytes written)
+    let code = [
+        base(0x1, 1, 0, 1),
+        base(0x1, 2, 0, 2),
+        base(0x1, 3, 0, 2),
+        base(0x1, 9, 0, SYS_WRITE as u8),
+        SYSCALL,
+        0xC000,
+    ];
+    let (mut m, out) = boot(&code, b"hi");
+    assert!(matches!(m.run(), StopReason::Exited(2)));
+    assert_eq!(&*out.0.borrow(), b"hi");
+}
+
+#[test]
+fn unhandled_traps_stop_the_machine() {
+    // div r3, r1, r2
+    let (mut m, _) = boot(&[0x2312, base(0x1, 4, 4, 1)], &[]);
+    assert!(matches!(m.run(), StopReason::Trap(Trap::DivideByZero)));
+    assert_eq!(m.cpu.tf.epcc.get_address(), TEXT);
+
+    // Stepping a stopped machine does nothing.
+    let cycle = m.cpu.cycle;
+    assert!(matches!(m.step(), Some(StopReason::Trap(Trap::DivideByZero))));
+    assert_eq!(m.cpu.cycle, cycle);
+    assert_eq!(m.cpu.r[4], 0);
+}
+
+#[test]
+fn run_for_can_be_resumed() {
+    // loop: addi r1, r1, 1 ; jmp r0, loop
+    let (mut m, _) = boot(&[base(0x1, 1, 1, 1), base(0x9, 0, 0, 0xE)], &[]);
+    assert!(matches!(m.run_for(10), StopReason::StepLimit));
+    assert_eq!(m.cpu.r[1], 5);
+    assert!(matches!(m.run_for(10), StopReason::StepLimit));
+    assert_eq!(m.cpu.r[1], 10);
+    assert!(m.step().is_none());
+}
+
+// r1 = malloc(7), exit(r1)
+fn malloc_exit() -> [u16; 7] {
+    [
+        base(0x1, 1, 0, 7),
+        0xB040,
+        base(0x1, 9, 0, 0),
+        SYS_MALLOC as u16,
+        SYSCALL,
+        base(0x1, 9, 0, SYS_EXIT as u8),
+        SYSCALL,
+    ]
+}
+
+#[test]
+fn load_resets_the_cpu() {
+    let (mut m, _) = boot(&[0x2312], &[]);
+    m.run();
+    m.load(&image(&[base(0x1, 1, 0, 3), 0xC000], &[])).unwrap();
+    assert!(!m.cpu.is_trapped());
+    assert!(matches!(m.run(), StopReason::Exited(3)));
+
+    // RAM and the heap start over too. The first program mallocs, then
+    // leaves the object's capability at 0(c2) and a byte in the object.
+    let mut first = malloc_exit()[..5].to_vec();
+    first.extend(ext(GROUP_CMEM, 0xC, 5, 2, 0));
+    first.extend(ext(GROUP_CMEM, 0x7, 9, 5, 0));
+    first.extend(&malloc_exit()[5..]);
+    m.load(&image(&first, &[0; 32])).unwrap();
+    let StopReason::Exited(obj) = m.run() else { panic!("first program did not exit") };
+    assert!(m.mem.tags[DATA as usize / 16]);
+    assert_ne!(m.mem.bytes[obj as usize], 0);
+
+    // The second reads the capability back and mallocs the same size.
+    let mut second = ext(GROUP_CMEM, 0xB, 4, 2, 0).to_vec();
+    second.extend(ext(GROUP_CAP, 0xA, 3, 4, 0));
+    second.extend(malloc_exit());
+    m.load(&image(&second, &[0; 32])).unwrap();
+    assert_eq!(m.mem.bytes[obj as usize], 0);
+
+    assert!(matches!(m.run(), StopReason::Exited(b) if b == obj), "the heap starts over");
+    assert_eq!(m.cpu.r[3], 0, "no capability survives the reload");
+    assert!(!m.cpu.c[4].valid);
+}
+
+#[test]
+fn bad_images_and_configs_are_rejected() {
+    let (mut m, _) = boot(&[], &[]);
+    let mut img = image(&[0xC000; 4], &[]);
+    img.truncate(img.len() - 1);
+    assert!(m.load(&img).is_err());
+    assert!(m.load(&img[..0x10]).is_err());
+
+    // RAM reaching into the device region.
+    let config = Config {
+        mem_size: MMIO_BASE as usize + 1,
+        serial: Some(Box::new(BufferSerial::new())),
+        ..Config::default()
+    };
+    assert!(Machine::new(config).is_err());
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::cpu::{LINK_CAP, PCC_INDEX};
+use hephaestus_isa::isa::GROUP_CAP;
+use hephaestus_isa::trap::Trap;
+
+const JMP_CAP: u8 = 0xE;
+const CALL_CAP: u8 = 0xF;
+
+/// Execute-only code capability over [TEXT + from, TEXT + from + len).
+fn code(from: u64, len: u64) -> Capability {
+    Capability {
+        base: TEXT + from,
+        length: len,
+        offset: 0,
+        perms: 0x04,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    }
+}
+
+#[test]
+fn register_jumps_stay_inside_the_pcc() {
+    // jmp r3, aimed below the code
+    let (mut cpu, mut mem) = machine(&[base(0x9, 0, 3, 0)]);
+    cpu.r[3] = TEXT - 0x10;
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+    assert_eq!((cpu.tf.badaddr, cpu.tf.cap), (TEXT - 0x10, PCC_INDEX));
+    assert_eq!(cpu.pcc.base, TEXT);
+}
+
+#[test]
+fn jmp_cap_replaces_the_pcc() {
+    // jmp c4, 2 ; addi r1, r1, 1 ; addi r2, r0, 5
+    let mut prog = ext(GROUP_CAP, JMP_CAP, 0, 4, 2).to_vec();
+    prog.extend([base(0x1, 1, 1, 1), base(0x1, 2, 0, 5)]);
+    let (mut cpu, mut mem) = machine(&prog);
+    cpu.c[4] = code(4, 4);
+    run(&mut cpu, &mut mem);
+
+    assert!(!cpu.is_trapped());
+    assert_eq!((cpu.r[1], cpu.r[2]), (0, 5));
+    assert_eq!(cpu.pcc, Capability { offset: 4, ..code(4, 4) });
+}
+
+#[test]
+fn jmp_cap_enforces_the_new_bounds() {
+    // The target covers only its first instruction; the next fetch faults.
+    let mut prog = ext(GROUP_CAP, JMP_CAP, 0, 4, 0).to_vec();
+    prog.extend([base(0x1, 2, 0, 5), base(0x1, 3, 0, 6)]);
+    let (mut cpu, mut mem) = machine(&prog);
+    cpu.c[4] = code(4, 2);
+    run(&mut cpu, &mut mem);
+
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+    assert_eq!((cpu.tf.badaddr, cpu.tf.cap), (TEXT + 6, PCC_INDEX));
+    assert_eq!((cpu.r[2], cpu.r[3]), (5, 0));
+}
+
+#[test]
+fn jmp_and_call_need_an_executable_unsealed_target() {
+    let bad = [
+        Capability { perms: 0x01, ..code(4, 2) },
+        Capability { valid: false, ..code(4, 2) },
+        Capability { sealed: true, otype: 1, ..code(4, 2) },
+    ];
+    for op in [JMP_CAP, CALL_CAP] {
+        for target in bad {
+            let mut prog = ext(GROUP_CAP, op, 0, 4, 0).to_vec();
+            prog.push(base(0x1, 2, 0, 5));
+            let (mut cpu, mut mem) = machine(&prog);
+            cpu.c[4] = target;
+            run(&mut cpu, &mut mem);
+
+            assert!(matches!(cpu.trap, Some(Trap::CapViolation)), "{op:#x} {target:?}");
+            assert_eq!(cpu.tf.cap, 4);
+            assert_eq!(cpu.pcc.base, TEXT);
+            assert!(!cpu.c[LINK_CAP].valid, "a failed call must not link");
+        }
+    }
+}
+
+// call c4 ; addi r1, r1, 1 ; jmp +3 ; callee: addi r2, r0, 7 ; jmp c6
+fn cap_call_program() -> Vec<u16> {
+    let mut prog = ext(GROUP_CAP, CALL_CAP, 0, 4, 0).to_vec();
+    prog.extend([base(0x1, 1, 1, 1), base(0x9, 0, 0, 3), base(0x1, 2, 0, 7)]);
+    prog.extend(ext(GROUP_CAP, JMP_CAP, 0, LINK_CAP as u8, 0));
+    prog
+}
+
+#[test]
+fn call_cap_links_and_jmp_c6_returns() {
+    let (mut cpu, mut mem) = machine(&cap_call_program());
+    let caller = cpu.pcc;
+    cpu.c[4] = code(8, 6);
+    run(&mut cpu, &mut mem);
+
+    assert!(!cpu.is_trapped());
+    assert_eq!((cpu.r[1], cpu.r[2]), (1, 7));
+    assert_eq!(cpu.c[LINK_CAP], Capability { offset: 4, ..caller });
+    assert_eq!((cpu.pcc.base, cpu.pcc.length), (caller.base, caller.length));
+}
+
+#[test]
+fn call_cap_enforces_the_callee_bounds() {
+    let (mut cpu, mut mem) = machine(&cap_call_program());
+    cpu.c[4] = code(8, 2);
+    run(&mut cpu, &mut mem);
+
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+    assert_eq!((cpu.tf.badaddr, cpu.tf.cap), (TEXT + 10, PCC_INDEX));
+    assert_eq!(cpu.r[1], 0);
+}
+
+// call +2 ; addi r1, r1, 1 ; jmp +2 ; addi r2, r0, 7 ; ret
+const CALL_RET: [u16; 5] = [0xA002, 0x1111, 0x9002, 0x1207, 0xB000];
+
+#[test]
+fn call_and_ret_link_through_the_link_capability() {
+    let (mut cpu, mut mem) = machine(&CALL_RET);
+    cpu.r[15] = 0x55;
+    run(&mut cpu, &mut mem);
+
+    assert!(!cpu.is_trapped());
+    assert_eq!((cpu.r[1], cpu.r[2]), (1, 7));
+    assert_eq!(cpu.r[15], 0x55);
+    assert_eq!(cpu.c[LINK_CAP], Capability { offset: 2, ..cpu.c[1] });
+}
+
+#[test]
+fn ret_through_a_non_code_link_faults() {
+    for link in [Capability::null(), Capability { offset: 2, perms: 0x01, ..code(0, 10) }] {
+        let (mut cpu, mut mem) = machine(&[0xB000]);
+        cpu.c[LINK_CAP] = link;
+        run(&mut cpu, &mut mem);
+        assert!(matches!(cpu.trap, Some(Trap::CapViolation)));
+        assert_eq!(cpu.tf.cap, LINK_CAP as u8);
+    }
+}
+
+#[test]
+fn ret_installs_the_link_bounds() {
+    // The link covers only the two words after ret; the third fetch faults.
+    let (mut cpu, mut mem) = machine(&[0xB000, 0x1105, 0x1206, 0x1307]);
+    cpu.c[LINK_CAP] = code(2, 4);
+    run(&mut cpu, &mut mem);
+
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+    assert_eq!((cpu.tf.badaddr, cpu.tf.cap), (TEXT + 6, PCC_INDEX));
+    assert_eq!((cpu.r[1], cpu.r[2], cpu.r[3]), (5, 6, 0));
+}
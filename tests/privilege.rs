@@ -0,0 +1,111 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::isa::{GROUP_CAP, GROUP_TRAP};
+use hephaestus_isa::trap::{Trap, trap_cause};
+
+const NO_SYSTEM: u64 = 0xFF & !0x20;
+
+// cap.andperm c4, c1, r1 ; jmp c4 -- enter user code at c[1]'s cursor.
+fn drop_to_user() -> Vec<u16> {
+    let mut code = ext(GROUP_CAP, 0x1, 4, 1, 1).to_vec();
+    code.extend(ext(GROUP_CAP, 0xE, 0, 4, 0));
+    code
+}
+
+#[test]
+fn loader_style_machine_starts_in_supervisor_mode() {
+    let (cpu, _) = machine(&[0]);
+    assert!(cpu.supervisor());
+}
+
+#[test]
+fn trap_group_is_privileged_in_user_mode() {
+    let mut code = drop_to_user();
+    code.extend(ext(GROUP_TRAP, 0x3, 1, 0, 0)); // cap.settvec c1
+    let (mut cpu, mut mem) = machine(&code);
+    cpu.r[1] = NO_SYSTEM;
+    cpu.c[1].offset = 8;
+    run(&mut cpu, &mut mem);
+
+    assert!(matches!(cpu.trap, Some(Trap::PrivilegeViolation)));
+    assert!(!cpu.supervisor());
+    assert_eq!(cpu.tf.epcc.get_address(), TEXT + 8);
+    assert!(!cpu.tvec.valid);
+}
+
+#[test]
+fn user_cannot_read_the_root_capability() {
+    let (mut cpu, mut mem) = machine(&ext(GROUP_TRAP, 0x5, 3, 0, 0));
+    cpu.pcc.perms = 4;
+    cpu.root = cpu.c[2];
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::PrivilegeViolation)));
+    assert!(!cpu.c[3].valid);
+
+    let (mut cpu, mut mem) = machine(&ext(GROUP_TRAP, 0x5, 3, 0, 0));
+    cpu.root = cpu.c[2];
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.c[3], cpu.root);
+}
+
+#[test]
+fn user_syscall_enters_the_kernel_and_returns() {
+    let mut code = drop_to_user();
+    // Kernel handler at byte 8: skip the syscall, set r7, return.
+    code.extend(ext(GROUP_TRAP, 0x1, 5, 0, 0));
+    code.extend(ext(GROUP_TRAP, 0x1, 6, 0, 4));
+    code.push(base(0x0, 5, 5, 6));
+    code.extend(ext(GROUP_TRAP, 0x2, 5, 0, 0));
+    code.push(base(0x1, 7, 0, 7));
+    code.extend(ext(GROUP_TRAP, 0x0, 0, 0, 0));
+    // User code at byte 28: syscall r0 ; addi r8, r8, 1
+    code.push(base(0xC, 0, 0, 0));
+    code.push(base(0x1, 8, 8, 1));
+
+    let (mut cpu, mut mem) = machine(&code);
+    cpu.r[1] = NO_SYSTEM;
+    cpu.c[1].offset = 28;
+    cpu.tvec = Capability { offset: 8, ..cpu.c[1] };
+    run(&mut cpu, &mut mem);
+
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.r[7], 7, "kernel saw the syscall");
+    assert_eq!(cpu.r[8], 1, "user code resumed");
+    assert_eq!(cpu.tf.cause, trap_cause(Trap::Syscall(0)));
+    assert!(!cpu.supervisor());
+}
+
+#[test]
+fn trap_vector_must_be_supervisor_code() {
+    // cap.andperm c4, c1, r1 ; cap.settvec c4
+    let mut code = ext(GROUP_CAP, 0x1, 4, 1, 1).to_vec();
+    code.extend(ext(GROUP_TRAP, 0x3, 4, 0, 0));
+    let (mut cpu, mut mem) = machine(&code);
+    cpu.r[1] = NO_SYSTEM;
+    run(&mut cpu, &mut mem);
+
+    assert!(matches!(cpu.trap, Some(Trap::CapViolation)));
+    assert_eq!(cpu.tf.cap, 4);
+    assert!(!cpu.tvec.valid);
+
+    // Clearing the vector with a null capability is still allowed.
+    let (mut cpu, mut mem) = machine(&ext(GROUP_TRAP, 0x3, 4, 0, 0));
+    cpu.tvec = cpu.c[1];
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert!(!cpu.tvec.valid);
+}
+
+#[test]
+fn user_mode_vector_is_not_entered() {
+    // A vector installed from the host without the system permission would
+    // run the handler in user mode, so the trap goes to the host instead.
+    let (mut cpu, mut mem) = machine(&[base(0x1, 1, 0, 1), base(0x2, 3, 1, 2)]);
+    cpu.tvec = Capability { perms: 0x04, ..cpu.c[1] };
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::DivideByZero)));
+    assert!(!cpu.tf.active);
+}
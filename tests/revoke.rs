@@ -0,0 +1,110 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::cpu::{CPU, STACK_CAP};
+use hephaestus_isa::isa::GROUP_CMEM;
+use hephaestus_isa::mem::Memory;
+use hephaestus_isa::revoke::Revoker;
+use hephaestus_isa::syscall::*;
+use hephaestus_isa::trap::Trap;
+
+// malloc(32) into c5; keep a copy in c3 and another at 0(c2); free(c5).
+fn alloc_copy_free() -> Vec<u16> {
+    let mut code = vec![SYSCALL, base(0xE, 3, 5, 0)];
+    code.extend(ext(GROUP_CMEM, 0xC, 5, 2, 0));
+    code.extend([base(0x1, 1, 0, 5), 0xC080]);
+    code
+}
+
+fn machine_with_heap(code: &[u16]) -> (CPU, Memory, Syscalls) {
+    let (mut cpu, mem) = machine(code);
+    cpu.c[STACK_CAP] = Capability { base: 0x8000, length: 0x1000, ..cpu.c[2] };
+    cpu.r[9] = SYS_MALLOC;
+    cpu.r[8] = SYS_FREE;
+    cpu.r[1] = 32;
+    let (env, _) = env(b"");
+    (cpu, mem, Syscalls::with_defaults(env))
+}
+
+#[test]
+fn dangling_register_copy_traps() {
+    // ld8u r4, 0(c3)
+    let mut code = alloc_copy_free();
+    code.extend(ext(GROUP_CMEM, 0x1, 4, 3, 0));
+    let (mut cpu, mut mem, mut sys) = machine_with_heap(&code);
+    run_sys(&mut cpu, &mut mem, &mut sys);
+
+    assert!(matches!(cpu.trap, Some(Trap::CapViolation)));
+    assert!(!cpu.c[3].valid && !cpu.c[5].valid);
+    assert_eq!(cpu.tf.cap, 3);
+}
+
+#[test]
+fn dangling_copy_in_memory_traps() {
+    // cap.load c4, 0(c2) ; st8 r0, 0(c4)
+    let mut code = alloc_copy_free();
+    code.extend(ext(GROUP_CMEM, 0xB, 4, 2, 0));
+    code.extend(ext(GROUP_CMEM, 0x7, 0, 4, 0));
+    let (mut cpu, mut mem, mut sys) = machine_with_heap(&code);
+    run_sys(&mut cpu, &mut mem, &mut sys);
+
+    assert!(matches!(cpu.trap, Some(Trap::CapViolation)));
+    assert!(!mem.tags[(DATA / 16) as usize]);
+    assert_eq!(cpu.tf.cap, 4);
+}
+
+#[test]
+fn batched_frees_wait_for_a_sweep() {
+    let (mut cpu, mut mem, mut sys) = machine_with_heap(&[]);
+    sys.env.revoke_batch = 1 << 20;
+
+    cpu.r[1] = 32;
+    sys.dispatch(SYS_MALLOC, &mut cpu, &mut mem);
+    let obj = cpu.c[SYS_CAP];
+    cpu.c[3] = obj;
+    cpu.r[1] = 3;
+    sys.dispatch(SYS_FREE, &mut cpu, &mut mem);
+    assert!(cpu.c[3].valid, "quarantined, not yet revoked");
+
+    // Quarantined memory is not handed out again before the sweep.
+    cpu.r[1] = 32;
+    sys.dispatch(SYS_MALLOC, &mut cpu, &mut mem);
+    assert_ne!(cpu.c[SYS_CAP].base, obj.base);
+
+    assert_eq!(sys.dispatch(SYS_REVOKE, &mut cpu, &mut mem), Outcome::Return(1));
+    assert!(!cpu.c[3].valid);
+    assert!(cpu.c[SYS_CAP].valid, "live objects are untouched");
+
+    cpu.r[1] = 32;
+    sys.dispatch(SYS_MALLOC, &mut cpu, &mut mem);
+    assert_eq!(cpu.c[SYS_CAP].base, obj.base);
+}
+
+#[test]
+fn sweep_reaches_all_machine_state() {
+    let (mut cpu, mut mem) = machine(&[]);
+    let freed = Capability { base: 0x4000, length: 0x40, ..cpu.c[2] };
+    let inside = Capability { base: 0x4020, length: 0x10, ..freed };
+    let around = Capability { base: 0x3FF0, length: 0x100, ..freed };
+
+    cpu.c[4] = inside;
+    cpu.c[5] = around;
+    cpu.tvec = freed;
+    cpu.tf.epcc = freed;
+    mem.store_cap(DATA, &inside, &cpu.c[2]).unwrap();
+    mem.store_cap(DATA + 16, &around, &cpu.c[2]).unwrap();
+
+    let mut r = Revoker::new(mem.bytes.len());
+    r.paint(0x4000, 0x40);
+    assert!(r.revoked(&inside) && !r.revoked(&around));
+    assert_eq!(r.sweep(&mut cpu, &mut mem), 4);
+
+    assert!(!cpu.c[4].valid && !cpu.tvec.valid && !cpu.tf.epcc.valid);
+    assert!(cpu.c[5].valid && cpu.c[2].valid && cpu.pcc.valid);
+    assert!(!mem.tags[(DATA / 16) as usize]);
+    assert!(mem.tags[(DATA / 16) as usize + 1]);
+
+    r.clear();
+    assert!(!r.revoked(&inside));
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::cpu::{CPU, MAX_FRAMES};
+use hephaestus_isa::isa::GROUP_CAP;
+use hephaestus_isa::mem::Memory;
+use hephaestus_isa::trap::Trap;
+
+const SEAL: u8 = 0x2;
+const UNSEAL: u8 = 0x3;
+const INVOKE: u8 = 0x4;
+const RETURN: u8 = 0x5;
+
+/// Sealing authority over object types [0, 0x100) with its cursor at `otype`.
+fn authority(otype: u64, perms: u8) -> Capability {
+    Capability {
+        base: 0,
+        length: 0x100,
+        offset: otype,
+        perms,
+        valid: true,
+        sealed: false,
+        otype: 0,
+    }
+}
+
+fn sealed(c: Capability, otype: u64) -> Capability {
+    Capability { sealed: true, otype, ..c }
+}
+
+/// Runs `code` with c3 = auth and c4 = a data capability.
+fn with_auth(code: &[u16], auth: Capability) -> (CPU, Memory) {
+    let (mut cpu, mut mem) = machine(code);
+    cpu.c[3] = auth;
+    cpu.c[4] = cpu.c[2];
+    run(&mut cpu, &mut mem);
+    (cpu, mem)
+}
+
+#[test]
+fn seal_then_unseal_round_trips() {
+    // cap.seal c5, c4, c3 ; cap.unseal c6, c5, c3
+    let mut code = ext(GROUP_CAP, SEAL, 5, 4, 3).to_vec();
+    code.extend(ext(GROUP_CAP, UNSEAL, 6, 5, 3));
+    let (cpu, _) = with_auth(&code, authority(0x42, 0xC0));
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.c[5], sealed(cpu.c[4], 0x42));
+    assert_eq!(cpu.c[6], cpu.c[4]);
+}
+
+#[test]
+fn otype_zero_is_an_ordinary_type() {
+    let mut code = ext(GROUP_CAP, SEAL, 5, 4, 3).to_vec();
+    code.extend(ext(GROUP_CAP, UNSEAL, 6, 5, 3));
+    let (cpu, _) = with_auth(&code, authority(0, 0xC0));
+    assert!(!cpu.is_trapped());
+    assert!(cpu.c[5].sealed);
+    assert_eq!(cpu.c[5].otype, 0);
+    assert_eq!(cpu.c[6], cpu.c[4]);
+
+    // An unsealed capability is not "sealed with type 0".
+    let (cpu, _) = with_auth(&ext(GROUP_CAP, UNSEAL, 6, 4, 3), authority(0, 0xC0));
+    assert!(matches!(cpu.trap, Some(Trap::CapViolation)));
+    assert_eq!(cpu.tf.cap, 4);
+}
+
+#[test]
+fn unseal_with_another_otype_faults() {
+    // cap.seal c5, c4, c3 ; cap.unseal c6, c5, c0 with c0 one type further
+    let mut code = ext(GROUP_CAP, SEAL, 5, 4, 3).to_vec();
+    code.extend(ext(GROUP_CAP, UNSEAL, 6, 5, 0));
+    let (mut cpu, mut mem) = machine(&code);
+    cpu.c[3] = authority(7, 0xC0);
+    cpu.c[0] = authority(8, 0xC0);
+    cpu.c[4] = cpu.c[2];
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::CapViolation)));
+    assert_eq!(cpu.tf.cap, 0);
+    assert!(!cpu.c[6].valid);
+}
+
+#[test]
+fn sealing_and_unsealing_need_their_permissions() {
+    let (cpu, _) = with_auth(&ext(GROUP_CAP, SEAL, 5, 4, 3), authority(1, 0x40));
+    assert!(matches!(cpu.trap, Some(Trap::CapViolation)));
+    assert_eq!(cpu.tf.cap, 3);
+
+    let (mut cpu, mut mem) = machine(&ext(GROUP_CAP, UNSEAL, 6, 5, 3));
+    cpu.c[3] = authority(1, 0x80);
+    cpu.c[5] = sealed(cpu.c[2], 1);
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::CapViolation)));
+    assert_eq!(cpu.tf.cap, 3);
+    assert!(!cpu.c[6].valid);
+}
+
+#[test]
+fn sealing_type_must_lie_inside_the_authority() {
+    let (cpu, _) = with_auth(&ext(GROUP_CAP, SEAL, 5, 4, 3), authority(0x100, 0xC0));
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+}
+
+// Caller: invoke c4, c5, then count r1 and jump past the callee. Callee: set
+// r2, read c2's base into r3 and return.
+fn compartment_call() -> Vec<u16> {
+    let mut code = ext(GROUP_CAP, INVOKE, 4, 5, 0).to_vec();
+    code.push(base(0x1, 1, 1, 1));
+    code.push(base(0x9, 0, 0, 5));
+    code.push(base(0x1, 2, 0, 7));
+    code.extend(ext(GROUP_CAP, 0x6, 3, 2, 0));
+    code.extend(ext(GROUP_CAP, RETURN, 0, 0, 0));
+    code
+}
+
+const CALLEE: u64 = 8;
+
+fn callee_pair(cpu: &CPU, code_otype: u64, data_otype: u64) -> (Capability, Capability) {
+    let code = Capability { offset: CALLEE, perms: 0x05, ..cpu.c[1] };
+    let data = Capability { base: DATA + 0x100, length: 0x100, ..cpu.c[2] };
+    (sealed(code, code_otype), sealed(data, data_otype))
+}
+
+#[test]
+fn invoke_enters_and_return_restores() {
+    let (mut cpu, mut mem) = machine(&compartment_call());
+    (cpu.c[4], cpu.c[5]) = callee_pair(&cpu, 9, 9);
+    let caller_data = cpu.c[2];
+    run(&mut cpu, &mut mem);
+
+    assert!(!cpu.is_trapped());
+    assert_eq!((cpu.r[1], cpu.r[2], cpu.r[3]), (1, 7, DATA + 0x100));
+    assert_eq!(cpu.c[2], caller_data);
+    assert_eq!(cpu.pcc.perms, 0x24);
+    assert!(cpu.frames.is_empty());
+}
+
+#[test]
+fn invoke_rejects_mismatched_or_unsealed_pairs() {
+    let (cpu, _) = machine(&compartment_call());
+    let (code, data) = callee_pair(&cpu, 9, 9);
+    let cases = [
+        callee_pair(&cpu, 9, 10),
+        (code, cpu.c[2]),
+        (Capability { sealed: false, otype: 0, ..code }, data),
+        // Code and data swapped: the "code" is not executable.
+        (data, code),
+    ];
+
+    for (k, pair) in cases.into_iter().enumerate() {
+        let (mut cpu, mut mem) = machine(&compartment_call());
+        (cpu.c[4], cpu.c[5]) = pair;
+        run(&mut cpu, &mut mem);
+        assert!(matches!(cpu.trap, Some(Trap::CapViolation)), "case {k}");
+        assert_eq!(cpu.tf.cap, 4, "case {k}");
+        assert!(cpu.frames.is_empty());
+        assert_eq!(cpu.r[2], 0);
+    }
+}
+
+#[test]
+fn return_without_invoke_faults() {
+    let (mut cpu, mut mem) = machine(&ext(GROUP_CAP, RETURN, 0, 0, 0));
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::CapViolation)));
+}
+
+#[test]
+fn invoke_nesting_is_capped() {
+    // A compartment that invokes itself forever.
+    let (mut cpu, mut mem) = machine(&ext(GROUP_CAP, INVOKE, 4, 5, 0));
+    cpu.c[4] = sealed(Capability { perms: 0x05, ..cpu.c[1] }, 3);
+    cpu.c[5] = sealed(cpu.c[2], 3);
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+    assert_eq!(cpu.tf.cap, 4);
+    assert_eq!(cpu.frames.len(), MAX_FRAMES);
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::cpu::{CPU, PCC_INDEX, STACK_CAP};
+use hephaestus_isa::isa::GROUP_STACK;
+use hephaestus_isa::mem::Memory;
+use hephaestus_isa::trap::Trap;
+
+const PCALL: u8 = 0x2;
+const PRET: u8 = 0x3;
+
+/// pcall to a subroutine that runs `body` and then pret. The caller counts r1
+/// on return and jumps past the subroutine.
+fn program(body: &[u16]) -> Vec<u16> {
+    let mut code = ext(GROUP_STACK, PCALL, 0, 0, 2).to_vec();
+    code.push(base(0x1, 1, 1, 1));
+    code.push(base(0x9, 0, 0, body.len() as u8 + 2));
+    code.extend(body);
+    code.extend(ext(GROUP_STACK, PRET, 0, 0, 0));
+    code
+}
+
+/// Sets the stack to DATA's first 0x100 bytes with the cursor at `sp`.
+fn with_stack(code: &[u16], sp: u64) -> (CPU, Memory) {
+    let (mut cpu, mut mem) = machine(code);
+    cpu.c[STACK_CAP] = Capability { length: 0x100, offset: sp, ..cpu.c[2] };
+    run(&mut cpu, &mut mem);
+    (cpu, mem)
+}
+
+#[test]
+fn pcall_and_pret_round_trip() {
+    let (cpu, mem) = with_stack(&program(&[base(0x1, 2, 0, 7)]), 0x100);
+    assert!(!cpu.is_trapped());
+    assert_eq!((cpu.r[1], cpu.r[2]), (1, 7));
+    assert_eq!(cpu.c[STACK_CAP].offset, 0x100);
+    let slot = DATA as usize + 0xF8;
+    assert_eq!(mem.bytes[slot..slot + 8], (TEXT + 4).to_le_bytes());
+}
+
+#[test]
+fn pcall_below_the_stack_base_faults() {
+    for sp in [0, 4] {
+        let (cpu, _) = with_stack(&program(&[base(0x1, 2, 0, 7)]), sp);
+        assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)), "sp {sp}");
+        assert_eq!((cpu.tf.badaddr, cpu.tf.cap), (DATA + sp - 8, STACK_CAP as u8));
+        assert_eq!(cpu.c[STACK_CAP].offset, sp, "stack pointer unchanged");
+        assert_eq!(cpu.tf.epcc.get_address(), TEXT);
+        assert_eq!(cpu.r[2], 0, "the call did not happen");
+    }
+}
+
+#[test]
+fn pret_above_the_stack_top_faults() {
+    for sp in [0x100, 0xFC] {
+        let (mut cpu, mut mem) = machine(&ext(GROUP_STACK, PRET, 0, 0, 0));
+        cpu.c[STACK_CAP] = Capability { length: 0x100, offset: sp, ..cpu.c[2] };
+        run(&mut cpu, &mut mem);
+        assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)), "sp {sp:#x}");
+        assert_eq!((cpu.tf.badaddr, cpu.tf.cap), (DATA + sp, STACK_CAP as u8));
+        assert_eq!(cpu.c[STACK_CAP].offset, sp);
+    }
+}
+
+#[test]
+fn tampered_return_address_stays_inside_the_pcc() {
+    // The subroutine overwrites its return address: st r3, 0(r4).
+    let (mut cpu, mut mem) = machine(&program(&[base(0x6, 3, 4, 0)]));
+    cpu.c[STACK_CAP] = Capability { length: 0x100, offset: 0x100, ..cpu.c[2] };
+    cpu.r[3] = TEXT - 0x10;
+    cpu.r[4] = DATA + 0xF8;
+    run(&mut cpu, &mut mem);
+
+    // pret goes where it was told, but the fetch there is checked against
+    // the unchanged PCC.
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+    assert_eq!((cpu.tf.badaddr, cpu.tf.cap), (TEXT - 0x10, PCC_INDEX));
+    assert_eq!((cpu.pcc.base, cpu.pcc.length), (TEXT, cpu.c[1].length));
+    assert_eq!(cpu.r[1], 0);
+}
@@ -0,0 +1,351 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::cpu::{CPU, STACK_CAP};
+use hephaestus_isa::isa::GROUP_CMEM;
+use hephaestus_isa::mem::Memory;
+use hephaestus_isa::syscall::*;
+use hephaestus_isa::trap::Trap;
+
+#[test]
+fn exit_stops_with_the_code() {
+    let (mut cpu, mut mem) = machine(&[SYSCALL, base(0x1, 1, 1, 1)]);
+    let (env, _) = env(b"");
+    let mut sys = Syscalls::with_defaults(env);
+    cpu.r[9] = SYS_EXIT;
+    cpu.r[1] = 42;
+    assert_eq!(run_sys(&mut cpu, &mut mem, &mut sys), Some(42));
+    assert_eq!(cpu.r[1], 42, "nothing runs after exit");
+}
+
+fn at(cpu: &CPU, addr: u64) -> Capability {
+    Capability { offset: addr - cpu.c[2].base, ..cpu.c[2] }
+}
+
+#[test]
+fn write_and_read_take_capability_buffers() {
+    let (mut cpu, mut mem) = machine(&[]);
+    let (env, out) = env(b"xyz");
+    let mut sys = Syscalls::with_defaults(env);
+    mem.bytes[DATA as usize..DATA as usize + 5].copy_from_slice(b"hello");
+
+    cpu.c[3] = at(&cpu, DATA);
+    cpu.r[1..4].copy_from_slice(&[1, 3, 5]);
+    assert_eq!(sys.dispatch(SYS_WRITE, &mut cpu, &mut mem), Outcome::Return(5));
+    assert_eq!(&*out.0.borrow(), b"hello");
+    assert_eq!(cpu.r[2], 0);
+
+    cpu.c[4] = at(&cpu, DATA + 8);
+    cpu.r[1..4].copy_from_slice(&[0, 4, 16]);
+    assert_eq!(sys.dispatch(SYS_READ, &mut cpu, &mut mem), Outcome::Return(3));
+    assert_eq!(&mem.bytes[DATA as usize + 8..DATA as usize + 11], b"xyz");
+}
+
+#[test]
+fn buffers_are_checked_before_any_copy() {
+    let (mut cpu, mut mem) = machine(&[]);
+    let (env, out) = env(b"xyz");
+    let mut sys = Syscalls::with_defaults(env);
+    let full = at(&cpu, DATA + DATA_SIZE - 4);
+    let cases = [
+        (SYS_WRITE, full, 5),
+        (SYS_WRITE, Capability { perms: 0x2, ..full }, 1),
+        (SYS_READ, Capability { perms: 0x1, ..full }, 1),
+        (SYS_READ, full, 8),
+        (SYS_READ, Capability { valid: false, ..full }, 1),
+        (SYS_READ, Capability { sealed: true, ..full }, 1),
+    ];
+
+    for (n, buf, len) in cases {
+        cpu.c[3] = buf;
+        cpu.r[1..4].copy_from_slice(&[if n == SYS_READ { 0 } else { 1 }, 3, len]);
+        sys.dispatch(n, &mut cpu, &mut mem);
+        assert_eq!((cpu.r[1], cpu.r[2]), (u64::MAX, EFAULT.0), "{n} {buf:?} {len}");
+    }
+    assert!(!cpu.is_trapped());
+    assert!(out.0.borrow().is_empty());
+    assert!(mem.bytes[DATA as usize..(DATA + DATA_SIZE) as usize].iter().all(|b| *b == 0));
+
+    // Only c0..c7 can be named.
+    cpu.r[1..4].copy_from_slice(&[1, 8, 0]);
+    sys.dispatch(SYS_WRITE, &mut cpu, &mut mem);
+    assert_eq!(cpu.r[2], EINVAL.0);
+}
+
+#[test]
+fn unknown_numbers_fail_with_enosys() {
+    let (mut cpu, mut mem) = machine(&[SYSCALL]);
+    let (env, _) = env(b"");
+    let mut sys = Syscalls::with_defaults(env);
+    cpu.r[9] = 999;
+    assert_eq!(run_sys(&mut cpu, &mut mem, &mut sys), None);
+    assert!(!cpu.is_trapped());
+    assert_eq!((cpu.r[1], cpu.r[2]), (u64::MAX, ENOSYS.0));
+}
+
+#[test]
+fn embedders_can_register_calls() {
+    let (mut cpu, mut mem) = machine(&[SYSCALL, base(0x1, 1, 1, 1)]);
+    let (env, _) = env(b"");
+    let mut sys = Syscalls::new(env);
+    let double = |_: &mut Env, cpu: &mut CPU, _: &mut Memory| Ok(Outcome::Return(cpu.r[1] * 2));
+    sys.register(100, Box::new(double));
+    cpu.r[9] = 100;
+    cpu.r[1] = 20;
+    run_sys(&mut cpu, &mut mem, &mut sys);
+    assert_eq!(cpu.r[1], 41);
+    assert_eq!(cpu.r[2], 0);
+}
+
+// Puts `name` at DATA and passes it in c3, bounded to exactly its length.
+#[cfg(unix)]
+fn path(cpu: &mut CPU, mem: &mut Memory, name: &[u8]) {
+    mem.bytes[DATA as usize..DATA as usize + name.len()].copy_from_slice(name);
+    cpu.c[3] = Capability { length: name.len() as u64, ..cpu.c[2] };
+    cpu.r[1] = 3;
+}
+
+#[cfg(unix)]
+#[test]
+fn open_stays_inside_the_root() {
+    // The sandbox root sits inside a per-process directory, so the dangling
+    // link can point outside the root without touching anything shared.
+    let dir = std::env::temp_dir().join(format!("heph-syscall-{}", std::process::id()));
+    let root = dir.join("root");
+    let nowhere = dir.join("nowhere");
+    std::fs::create_dir_all(&root).unwrap();
+    std::os::unix::fs::symlink("/etc/hostname", root.join("escape")).unwrap();
+    std::os::unix::fs::symlink(&nowhere, root.join("dangling")).unwrap();
+
+    let (mut cpu, mut mem) = machine(&[]);
+    let (mut env, _) = env(b"");
+    env.root = root.clone();
+    let mut sys = Syscalls::with_defaults(env);
+
+    path(&mut cpu, &mut mem, b"out.txt\0");
+    cpu.r[2] = O_WRONLY | O_CREAT | O_TRUNC;
+    sys.dispatch(SYS_OPEN, &mut cpu, &mut mem);
+    let fd = cpu.r[1];
+    assert_eq!((fd, cpu.r[2]), (3, 0));
+
+    mem.bytes[DATA as usize + 0x200..DATA as usize + 0x202].copy_from_slice(b"ok");
+    cpu.c[4] = at(&cpu, DATA + 0x200);
+    cpu.r[1..4].copy_from_slice(&[fd, 4, 2]);
+    assert_eq!(sys.dispatch(SYS_WRITE, &mut cpu, &mut mem), Outcome::Return(2));
+    cpu.r[1] = fd;
+    sys.dispatch(SYS_CLOSE, &mut cpu, &mut mem);
+    assert_eq!(cpu.r[2], 0);
+    cpu.r[1] = fd;
+    sys.dispatch(SYS_CLOSE, &mut cpu, &mut mem);
+    assert_eq!(cpu.r[2], EBADF.0);
+    assert_eq!(std::fs::read(root.join("out.txt")).unwrap(), b"ok");
+
+    for name in [&b"../x\0"[..], b"/etc/hostname\0", b"escape\0", b"dangling\0"] {
+        path(&mut cpu, &mut mem, name);
+        cpu.r[2] = O_RDWR | O_CREAT;
+        sys.dispatch(SYS_OPEN, &mut cpu, &mut mem);
+        assert_eq!(cpu.r[2], EACCES.0, "{}", String::from_utf8_lossy(name));
+    }
+    assert!(!nowhere.exists());
+
+    // The name must be terminated inside the capability.
+    path(&mut cpu, &mut mem, b"out.txt\0");
+    cpu.c[3].length = 7;
+    sys.dispatch(SYS_OPEN, &mut cpu, &mut mem);
+    assert_eq!(cpu.r[2], EFAULT.0);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn brk_widens_only_the_loader_data_capability() {
+    let (mut cpu, mut mem) = machine(&[]);
+    let (env, _) = env(b"");
+    let mut sys = Syscalls::with_defaults(env);
+    cpu.c[STACK_CAP] = Capability { base: 0x8000, length: 0x1000, ..cpu.c[2] };
+    sys.env.attach(&cpu);
+    let loader_data = cpu.c[2];
+    let end = DATA + DATA_SIZE;
+
+    // Stale data and a tag left in the gap the break grows over.
+    mem.bytes[end as usize + 0x20] = 0x5A;
+    mem.tags[end as usize / 16] = true;
+
+    // The program swaps c[2] for a wider read-only capability of its own.
+    cpu.c[2] = Capability { base: 0, length: 0x8000, perms: 0x01, ..loader_data };
+    cpu.r[1] = end + 0x40;
+    assert_eq!(sys.dispatch(SYS_BRK, &mut cpu, &mut mem), Outcome::Return(end + 0x40));
+    assert_eq!(cpu.c[SYS_CAP], Capability { length: DATA_SIZE + 0x40, ..loader_data });
+    assert_eq!(cpu.c[2].length, 0x8000);
+    assert!(!mem.tags[end as usize / 16]);
+    assert!(mem.bytes[end as usize..end as usize + 0x40].iter().all(|b| *b == 0));
+
+    // Querying hands the same capability back.
+    cpu.c[SYS_CAP] = Capability::null();
+    cpu.r[1] = 0;
+    assert_eq!(sys.dispatch(SYS_BRK, &mut cpu, &mut mem), Outcome::Return(end + 0x40));
+    assert_eq!(cpu.c[SYS_CAP].length, DATA_SIZE + 0x40);
+}
+
+#[test]
+fn brk_and_mmap_carve_up_the_heap() {
+    let (mut cpu, mut mem) = machine(&[]);
+    let (env, _) = env(b"");
+    let mut sys = Syscalls::with_defaults(env);
+    cpu.c[STACK_CAP] = Capability { base: 0x8000, length: 0x1000, ..cpu.c[2] };
+    let end = DATA + DATA_SIZE;
+
+    cpu.r[1] = 0;
+    assert_eq!(sys.dispatch(SYS_BRK, &mut cpu, &mut mem), Outcome::Return(end));
+    cpu.r[1] = end + 0x100;
+    assert_eq!(sys.dispatch(SYS_BRK, &mut cpu, &mut mem), Outcome::Return(end + 0x100));
+    assert_eq!(cpu.c[SYS_CAP], Capability { length: DATA_SIZE + 0x100, ..cpu.c[2] });
+    assert_eq!(cpu.c[2].length, DATA_SIZE, "c[2] itself is left alone");
+
+    cpu.r[1] = 100;
+    assert_eq!(sys.dispatch(SYS_MMAP, &mut cpu, &mut mem), Outcome::Return(0x7000));
+    let c = cpu.c[SYS_CAP];
+    assert!(c.valid);
+    assert_eq!((c.base, c.length), (0x7000, 100));
+
+    // The break cannot grow into the mapping.
+    cpu.r[1] = 0x7001;
+    sys.dispatch(SYS_BRK, &mut cpu, &mut mem);
+    assert_eq!(cpu.r[2], ENOMEM.0);
+    cpu.r[1] = 0x10000;
+    sys.dispatch(SYS_MMAP, &mut cpu, &mut mem);
+    assert_eq!(cpu.r[2], ENOMEM.0);
+}
+
+#[test]
+fn mmap_bounds_survive_a_store() {
+    let (mut cpu, mut mem) = machine(&[]);
+    let (env, _) = env(b"");
+    let mut sys = Syscalls::with_defaults(env);
+    cpu.c[STACK_CAP] = Capability { base: 0x10000, length: 0, ..cpu.c[2] };
+
+    // 0xC001 bytes is too long to encode exactly.
+    cpu.r[1] = 0xC001;
+    sys.dispatch(SYS_MMAP, &mut cpu, &mut mem);
+    assert_eq!(cpu.r[2], 0);
+    let map = cpu.c[SYS_CAP];
+    assert_eq!((map.base, map.length), (0x3000, 0xC002));
+    mem.store_cap(DATA, &map, &cpu.c[2]).unwrap();
+    assert_eq!(mem.load_cap(DATA, &cpu.c[2]).unwrap(), map);
+}
+
+#[test]
+fn malloc_hands_out_bounded_objects() {
+    // malloc(4); st32 r3, 0(c5); st32 r3, 4(c5)
+    let mut code = vec![SYSCALL];
+    code.extend(ext(GROUP_CMEM, 0x9, 3, 5, 0));
+    code.extend(ext(GROUP_CMEM, 0x9, 3, 5, 4));
+    let (mut cpu, mut mem) = machine(&code);
+    let (env, _) = env(b"");
+    let mut sys = Syscalls::with_defaults(env);
+    cpu.c[STACK_CAP] = Capability { base: 0x8000, length: 0x1000, ..cpu.c[2] };
+    cpu.r[9] = SYS_MALLOC;
+    cpu.r[1] = 4;
+    cpu.r[3] = 0xAABB_CCDD;
+
+    run_sys(&mut cpu, &mut mem, &mut sys);
+    let obj = cpu.c[SYS_CAP];
+    assert_eq!((obj.length, obj.perms & 3), (4, 3));
+    assert_eq!(obj.base % 16, 0);
+    assert_eq!(mem.bytes[obj.base as usize], 0xDD);
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+}
+
+#[test]
+fn malloc_bounds_survive_a_store() {
+    let (mut cpu, mut mem) = machine(&[]);
+    let (env, _) = env(b"");
+    let mut sys = Syscalls::with_defaults(env);
+    cpu.c[STACK_CAP] = Capability { base: 0x10000, length: 0, ..cpu.c[2] };
+
+    let mut objs = Vec::new();
+    for len in [1, 100, 0xC001, 24] {
+        cpu.r[1] = len;
+        sys.dispatch(SYS_MALLOC, &mut cpu, &mut mem);
+        assert_eq!(cpu.r[2], 0, "malloc({len})");
+        let obj = cpu.c[SYS_CAP];
+        assert!(obj.length >= len && obj.length < len + len / 1000 + 16);
+        mem.store_cap(DATA, &obj, &cpu.c[2]).unwrap();
+        assert_eq!(mem.load_cap(DATA, &cpu.c[2]).unwrap(), obj);
+        objs.push(obj);
+    }
+
+    objs.sort_by_key(|c| c.base);
+    for w in objs.windows(2) {
+        assert!(w[0].base + w[0].length <= w[1].base, "{:?} overlaps {:?}", w[0], w[1]);
+    }
+    assert!(objs[0].base >= DATA + DATA_SIZE);
+}
+
+#[test]
+fn free_revokes_the_object() {
+    let (mut cpu, mut mem) = machine(&[]);
+    let (env, _) = env(b"");
+    let mut sys = Syscalls::with_defaults(env);
+    cpu.c[STACK_CAP] = Capability { base: 0x8000, length: 0x1000, ..cpu.c[2] };
+
+    cpu.r[1] = 64;
+    sys.dispatch(SYS_MALLOC, &mut cpu, &mut mem);
+    let obj = cpu.c[SYS_CAP];
+    mem.store_cap(obj.base, &cpu.c[2], &obj).unwrap();
+    mem.store8(obj.base + 20, 7, &obj).unwrap();
+
+    cpu.c[3] = Capability { offset: 8, ..obj };
+    cpu.r[1] = 3;
+    assert_eq!(sys.dispatch(SYS_FREE, &mut cpu, &mut mem), Outcome::Return(0));
+    assert!(!cpu.c[3].valid);
+    assert!(!mem.tags[(obj.base / 16) as usize]);
+    assert_eq!(mem.bytes[obj.base as usize + 20], 0);
+
+    // Double free, a capability that is not an object, an untagged one.
+    cpu.c[3] = obj;
+    cpu.r[1] = 3;
+    sys.dispatch(SYS_FREE, &mut cpu, &mut mem);
+    assert_eq!(cpu.r[2], EINVAL.0);
+    cpu.c[3] = cpu.c[2];
+    cpu.r[1] = 3;
+    sys.dispatch(SYS_FREE, &mut cpu, &mut mem);
+    assert_eq!(cpu.r[2], EINVAL.0);
+    cpu.c[3] = Capability { valid: false, ..obj };
+    cpu.r[1] = 3;
+    sys.dispatch(SYS_FREE, &mut cpu, &mut mem);
+    assert_eq!(cpu.r[2], EFAULT.0);
+
+    // The block is reused.
+    cpu.r[1] = 48;
+    sys.dispatch(SYS_MALLOC, &mut cpu, &mut mem);
+    assert_eq!(cpu.c[SYS_CAP].base, obj.base);
+}
+
+#[test]
+fn free_needs_the_whole_writable_object() {
+    let (mut cpu, mut mem) = machine(&[]);
+    let (env, _) = env(b"");
+    let mut sys = Syscalls::with_defaults(env);
+    cpu.c[STACK_CAP] = Capability { base: 0x8000, length: 0x1000, ..cpu.c[2] };
+
+    cpu.r[1] = 64;
+    sys.dispatch(SYS_MALLOC, &mut cpu, &mut mem);
+    let obj = cpu.c[SYS_CAP];
+    mem.store8(obj.base + 40, 7, &obj).unwrap();
+
+    // Same base, but only part of the object; then the whole of it read-only.
+    for c in [Capability { length: 32, ..obj }, Capability { perms: 0x01, ..obj }] {
+        cpu.c[3] = c;
+        cpu.r[1] = 3;
+        sys.dispatch(SYS_FREE, &mut cpu, &mut mem);
+        assert_eq!(cpu.r[2], EINVAL.0, "{c:?}");
+        assert_eq!(mem.bytes[obj.base as usize + 40], 7, "object is still live");
+    }
+
+    cpu.c[3] = obj;
+    cpu.r[1] = 3;
+    assert_eq!(sys.dispatch(SYS_FREE, &mut cpu, &mut mem), Outcome::Return(0));
+    assert_eq!(mem.bytes[obj.base as usize + 40], 0);
+}
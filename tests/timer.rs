@@ -0,0 +1,75 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::isa::{GROUP_COUNTER, GROUP_TRAP};
+use hephaestus_isa::trap::{Trap, trap_cause};
+
+#[test]
+fn counters_track_steps_and_retired_instructions() {
+    let mut code = vec![base(0x1, 5, 5, 1); 3];
+    code.extend(ext(GROUP_COUNTER, 0x0, 1, 0, 0));
+    code.extend(ext(GROUP_COUNTER, 0x1, 2, 0, 0));
+    let (mut cpu, mut mem) = machine(&code);
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.r[1], 4);
+    assert_eq!(cpu.r[2], 4);
+    assert_eq!((cpu.cycle, cpu.instret), (5, 5));
+}
+
+#[test]
+fn faulting_instruction_does_not_retire() {
+    // div r3, r1, r2 traps; the handler skips it.
+    let mut code = vec![0x2312];
+    code.extend(ext(GROUP_TRAP, 0x1, 5, 0, 0));
+    code.push(base(0x1, 5, 5, 2));
+    code.extend(ext(GROUP_TRAP, 0x2, 5, 0, 0));
+    code.extend(ext(GROUP_TRAP, 0x0, 0, 0, 0));
+    let (mut cpu, mut mem) = machine(&code);
+    cpu.tvec = Capability { offset: 2, ..cpu.c[1] };
+    cpu.run_until(&mut mem, 5);
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.cycle, 5);
+    assert_eq!(cpu.instret, 4);
+}
+
+#[test]
+fn timer_preempts_at_an_exact_cycle() {
+    let mut code = vec![
+        base(0x1, 1, 1, 1),    // loop: addi r1, r1, 1
+        base(0x9, 0, 0, 0xE),  // jmp r0, loop
+    ];
+    // Handler: r3 = cause; r2 = r1; disarm the timer; eret
+    code.extend(ext(GROUP_TRAP, 0x1, 3, 0, 1));
+    code.push(base(0x0, 2, 1, 0));
+    code.extend(ext(GROUP_TRAP, 0x6, 4, 0, 0));
+    code.extend(ext(GROUP_TRAP, 0x0, 0, 0, 0));
+
+    let (mut cpu, mut mem) = machine(&code);
+    cpu.tvec = Capability { offset: 4, ..cpu.c[1] };
+    cpu.r[4] = u64::MAX;
+    cpu.timecmp = 10;
+
+    cpu.run_until(&mut mem, 11);
+    assert!(cpu.tf.active, "interrupt taken on the eleventh step");
+    assert_eq!(cpu.tf.epcc.get_address(), TEXT);
+    assert_eq!(cpu.instret, 10);
+
+    assert_eq!(cpu.run_until(&mut mem, 40), 29);
+    assert!(!cpu.is_trapped());
+    assert!(!cpu.tf.active);
+    assert_eq!(cpu.r[3], trap_cause(Trap::TimerInterrupt));
+    assert_eq!(cpu.r[2], 5, "five loop iterations ran before the interrupt");
+    assert_eq!(cpu.timecmp, u64::MAX);
+    assert!(cpu.r[1] > 5, "loop resumed after eret");
+}
+
+#[test]
+fn timer_waits_for_a_handler() {
+    let (mut cpu, mut mem) = machine(&[base(0x1, 1, 1, 1); 4]);
+    cpu.timecmp = 0;
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.r[1], 4);
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::cpu::{NO_CAP, PCC_INDEX};
+use hephaestus_isa::isa::{GROUP_CAP, GROUP_CMEM, GROUP_MEM, GROUP_TRAP};
+use hephaestus_isa::trap::{Trap, trap_cause};
+
+const DIV_R3_R1_R2: u16 = 0x2312;
+
+fn trap_get(rd: u8, field: u8) -> [u16; 2] {
+    ext(GROUP_TRAP, 0x1, rd, 0, field)
+}
+
+fn eret() -> [u16; 2] {
+    ext(GROUP_TRAP, 0x0, 0, 0, 0)
+}
+
+#[test]
+fn unhandled_trap_fills_the_frame() {
+    let (mut cpu, mut mem) = machine(&[base(0x1, 1, 0, 1), DIV_R3_R1_R2]);
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::DivideByZero)));
+    assert_eq!(cpu.tf.epcc.get_address(), TEXT + 2);
+    assert_eq!(cpu.tf.cause, trap_cause(Trap::DivideByZero));
+    assert_eq!(cpu.tf.len, 2);
+    assert_eq!(cpu.tf.cap, NO_CAP);
+    assert!(!cpu.tf.active);
+}
+
+#[test]
+fn handler_skips_faulting_divide() {
+    let mut code = vec![
+        DIV_R3_R1_R2,
+        base(0x1, 4, 4, 1),    // addi r4, r4, 1
+        0xB040, base(0x9, 0, 0, 0), 10,    // jmp r0, past the handler
+    ];
+    // Handler: epc += len; r7 = 7; eret
+    code.extend(trap_get(5, 0));
+    code.extend(trap_get(6, 4));
+    code.push(base(0x0, 5, 5, 6));
+    code.extend(ext(GROUP_TRAP, 0x2, 5, 0, 0));
+    code.push(base(0x1, 7, 0, 7));
+    code.extend(eret());
+
+    let (mut cpu, mut mem) = machine(&code);
+    cpu.r[1] = 10;
+    cpu.tvec = Capability { offset: 10, ..cpu.c[1] };
+    run(&mut cpu, &mut mem);
+
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.r[3], 0);
+    assert_eq!(cpu.r[4], 1, "execution resumed after the divide");
+    assert_eq!(cpu.r[7], 7, "handler ran");
+    assert_eq!(cpu.tf.cause, trap_cause(Trap::DivideByZero));
+    assert!(!cpu.tf.active);
+}
+
+#[test]
+fn memory_fault_reports_address_and_capability() {
+    // ld8u r1, 0(r2)
+    let (mut cpu, mut mem) = machine(&ext(GROUP_MEM, 0x1, 1, 2, 0));
+    cpu.r[2] = DATA + DATA_SIZE;
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+    assert_eq!(cpu.tf.badaddr, DATA + DATA_SIZE);
+    assert_eq!(cpu.tf.cap, 2);
+
+    // ld8u r1, -1(c4)
+    let (mut cpu, mut mem) = machine(&ext(GROUP_CMEM, 0x1, 1, 4, 0xF));
+    cpu.c[4] = cpu.c[2];
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+    assert_eq!(cpu.tf.badaddr, DATA - 1);
+    assert_eq!(cpu.tf.cap, 4);
+}
+
+#[test]
+fn fetch_fault_blames_the_pcc() {
+    // jmp r0, +2 lands past the end of the code
+    let (mut cpu, mut mem) = machine(&[base(0x9, 0, 0, 2)]);
+    cpu.step(&mut mem);
+    cpu.step(&mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::OutOfBounds)));
+    assert_eq!(cpu.tf.badaddr, TEXT + 6);
+    assert_eq!(cpu.tf.cap, PCC_INDEX);
+    assert_eq!(cpu.tf.len, 0);
+}
+
+#[test]
+fn illegal_instruction_reports_its_words() {
+    // A prefix with reserved bits set.
+    let (mut cpu, mut mem) = machine(&[0xB001, 0x1234]);
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::IllegalInstruction)));
+    assert_eq!(cpu.tf.badaddr, 0x1234_B001);
+    assert_eq!(cpu.tf.len, 4);
+}
+
+#[test]
+fn trap_inside_handler_goes_to_the_host() {
+    // The handler divides by zero again.
+    let (mut cpu, mut mem) = machine(&[DIV_R3_R1_R2, DIV_R3_R1_R2]);
+    cpu.tvec = Capability { offset: 2, ..cpu.c[1] };
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::DivideByZero)));
+    assert!(cpu.tf.active);
+    assert_eq!(cpu.tf.epcc.get_address(), TEXT + 2);
+}
+
+#[test]
+fn eret_outside_handler_is_illegal() {
+    let (mut cpu, mut mem) = machine(&eret());
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::IllegalInstruction)));
+}
+
+#[test]
+fn syscalls_bypass_the_handler() {
+    // syscall r0
+    let (mut cpu, mut mem) = machine(&[base(0xC, 0, 0, 0)]);
+    cpu.tvec = cpu.c[1];
+    run(&mut cpu, &mut mem);
+    assert!(matches!(cpu.trap, Some(Trap::Syscall(0))));
+    assert!(!cpu.tf.active);
+}
+
+#[test]
+fn handler_syscall_keeps_the_fault_details() {
+    // ld8u r1, 0(r2) faults; the handler makes a syscall, then reads the
+    // frame's badaddr and capability.
+    let mut code = ext(GROUP_MEM, 0x1, 1, 2, 0).to_vec();
+    code.push(SYSCALL);
+    code.extend(trap_get(5, 2));
+    code.extend(trap_get(6, 3));
+
+    let (mut cpu, mut mem) = machine(&code);
+    cpu.r[2] = DATA + DATA_SIZE;
+    cpu.tvec = Capability { offset: 4, ..cpu.c[1] };
+    while !cpu.is_trapped() {
+        cpu.step(&mut mem);
+    }
+    assert!(matches!(cpu.trap, Some(Trap::Syscall(_))));
+    assert!(cpu.tf.active);
+    assert_eq!((cpu.tf.badaddr, cpu.tf.cap), (DATA + DATA_SIZE, 2));
+
+    cpu.trap = None;
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert_eq!((cpu.r[5], cpu.r[6]), (DATA + DATA_SIZE, 2));
+    assert_eq!(cpu.tf.cause, trap_cause(Trap::OutOfBounds));
+}
+
+#[test]
+fn user_syscall_clears_stale_fault_details() {
+    // A leftover fault address must not show up in a syscall's frame.
+    let mut code = ext(GROUP_CAP, 0x1, 4, 1, 1).to_vec();
+    code.extend(ext(GROUP_CAP, 0xE, 0, 4, 0));
+    code.push(SYSCALL);
+    let (mut cpu, mut mem) = machine(&code);
+    cpu.r[1] = 0xFF & !0x20;
+    cpu.c[1].offset = 8;
+    cpu.tf.badaddr = 0x1234;
+    cpu.tf.cap = 3;
+    run(&mut cpu, &mut mem);
+
+    assert!(matches!(cpu.trap, Some(Trap::Syscall(_))));
+    assert_eq!(cpu.tf.cause, trap_cause(Trap::Syscall(0)));
+    assert_eq!((cpu.tf.badaddr, cpu.tf.cap), (0, NO_CAP));
+}
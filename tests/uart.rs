@@ -0,0 +1,62 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::bus::Device;
+use hephaestus_isa::cap::Capability;
+use hephaestus_isa::devices::uart::{self, BufferSerial, Uart};
+use hephaestus_isa::isa::{GROUP_ALU, GROUP_CMEM};
+
+const UART: u64 = 0x10_0000;
+
+#[test]
+fn status_reflects_the_buffers() {
+    let line = BufferSerial::new();
+    let mut dev = Uart::new(Box::new(line.clone()));
+
+    assert_eq!(dev.read(uart::STATUS, 4), uart::TX_READY);
+    assert_eq!(dev.read(uart::DATA, 1), 0);
+
+    line.push_input(b"ab");
+    assert_eq!(dev.read(uart::STATUS, 4), uart::TX_READY | uart::RX_READY);
+    assert_eq!(dev.read(uart::DATA, 1), b'a' as u64);
+
+    line.close_input();
+    assert_eq!(dev.read(uart::STATUS, 4) & uart::RX_CLOSED, 0, "not drained yet");
+    assert_eq!(dev.read(uart::DATA, 1), b'b' as u64);
+    assert_eq!(dev.read(uart::STATUS, 4), uart::TX_READY | uart::RX_CLOSED);
+
+    dev.write(uart::DATA, 1, b'z' as u64);
+    assert_eq!(line.output(), b"z");
+}
+
+#[test]
+fn guest_echoes_input_until_closed() {
+    let mut code = Vec::new();
+    // loop: status = ld8u 4(c4)
+    code.extend(ext(GROUP_CMEM, 0x1, 1, 4, 4));
+    // if !(status & RX_READY) goto closed
+    code.extend(ext(GROUP_ALU, 0x0, 3, 1, 2));
+    code.push(base(0x8, 0, 3, 7));
+    // st8 (ld8u 0(c4)), 0(c4)
+    code.extend(ext(GROUP_CMEM, 0x1, 5, 4, 0));
+    code.extend(ext(GROUP_CMEM, 0x7, 5, 4, 0));
+    // goto loop
+    code.extend([0xB040, base(0x9, 0, 0, 0), -12i16 as u16]);
+    // closed: if !(status & RX_CLOSED) goto loop
+    code.extend(ext(GROUP_ALU, 0x0, 3, 1, 6));
+    code.extend([0xB040, base(0x8, 0, 3, 0), -17i16 as u16]);
+
+    let (mut cpu, mut mem) = machine(&code);
+    let line = BufferSerial::new();
+    mem.map(UART, 0x100, Box::new(Uart::new(Box::new(line.clone())))).unwrap();
+    cpu.c[4] = Capability { base: UART, length: 0x100, ..cpu.c[2] };
+    cpu.r[2] = uart::RX_READY;
+    cpu.r[6] = uart::RX_CLOSED;
+
+    line.push_input(b"hello\n");
+    line.close_input();
+    run(&mut cpu, &mut mem);
+
+    assert!(!cpu.is_trapped());
+    assert_eq!(line.output(), b"hello\n");
+}
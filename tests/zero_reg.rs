@@ -0,0 +1,63 @@
+mod common;
+
+use common::*;
+use hephaestus_isa::isa::{GROUP_ALU, GROUP_IMM, GROUP_MEM, GROUP_STACK};
+use hephaestus_isa::cap::Capability;
+
+#[test]
+fn addi_to_r0_is_discarded() {
+    // addi r0, r0, 1 ; addi r1, r0, 2
+    let (mut cpu, mut mem) = machine(&[base(0x1, 0, 0, 1), base(0x1, 1, 0, 2)]);
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.r[0], 0);
+    assert_eq!(cpu.r[1], 2);
+}
+
+#[test]
+fn extension_ops_cannot_write_r0() {
+    let mut code = wide(GROUP_IMM, 0x0, 0, 0, -1);
+    code.extend(ext(GROUP_ALU, 0x3, 0, 1, 0));
+    let (mut cpu, mut mem) = machine(&code);
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.r[0], 0);
+}
+
+#[test]
+fn load_into_r0_is_discarded() {
+    // ld8u r0, 0(r1) with r1 pointing at a non-zero byte
+    let (mut cpu, mut mem) = machine(&ext(GROUP_MEM, 0x1, 0, 1, 0));
+    mem.bytes[DATA as usize] = 0x5A;
+    cpu.r[1] = DATA;
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.r[0], 0);
+}
+
+#[test]
+fn pop_into_r0_still_moves_the_stack() {
+    let [p, w] = ext(GROUP_STACK, 0x1, 0, 0, 0);
+    let (mut cpu, mut mem) = machine(&[p, w]);
+    cpu.c[7] = Capability { offset: 0x100 - 8, ..cpu.c[2] };
+    mem.bytes[(DATA + 0x100 - 8) as usize] = 0x77;
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.r[0], 0);
+    assert_eq!(cpu.c[7].offset, 0x100);
+}
+
+#[test]
+fn pc_relative_jump_survives_r0_write() {
+    // addi r0, r0, 5 ; jmp r0, +1 ; addi r1, r1, 1 ; addi r2, r2, 1
+    let (mut cpu, mut mem) = machine(&[
+        base(0x1, 0, 0, 5),
+        base(0x9, 0, 0, 1),
+        base(0x1, 1, 1, 1),
+        base(0x1, 2, 2, 1),
+    ]);
+    run(&mut cpu, &mut mem);
+    assert!(!cpu.is_trapped());
+    assert_eq!(cpu.r[1], 0, "jump should have skipped this");
+    assert_eq!(cpu.r[2], 1);
+}